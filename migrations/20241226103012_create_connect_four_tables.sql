@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS connect_four_games (
+    id UUID PRIMARY KEY,
+    width INT NOT NULL,
+    height INT NOT NULL,
+    win_length INT NOT NULL,
+    strict BOOLEAN NOT NULL,
+    opponent TEXT NOT NULL,
+    depth INT NOT NULL,
+    position TEXT,
+    status TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    finished_at TIMESTAMPTZ
+);
+
+CREATE TABLE IF NOT EXISTS connect_four_moves (
+    game_id UUID NOT NULL REFERENCES connect_four_games (id) ON DELETE CASCADE,
+    seq INT NOT NULL,
+    team TEXT NOT NULL,
+    col INT NOT NULL,
+    PRIMARY KEY (game_id, seq)
+);
@@ -0,0 +1,3 @@
+-- When the current round of a game began, i.e. its last reset, load or random board.
+ALTER TABLE connect_four_games ADD COLUMN IF NOT EXISTS started_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP;
+UPDATE connect_four_games SET started_at = created_at;
@@ -0,0 +1,13 @@
+ALTER TABLE quotes ADD COLUMN IF NOT EXISTS search TSVECTOR GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', author), 'A') || setweight(to_tsvector('english', quote), 'B')
+) STORED;
+
+CREATE INDEX IF NOT EXISTS quotes_search_idx ON quotes USING GIN (search);
+
+-- Quotes are plain text, so they have to be escaped before search results mark them up as HTML.
+CREATE OR REPLACE FUNCTION escape_html(text TEXT) RETURNS TEXT
+    LANGUAGE SQL IMMUTABLE
+    AS $$
+        SELECT replace(replace(replace(replace(replace(
+            text, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '"', '&quot;'), '''', '&#39;')
+    $$;
@@ -0,0 +1,9 @@
+-- Earlier versions of quotes, recorded whenever a quote is changed or deleted.
+CREATE TABLE IF NOT EXISTS quote_versions (
+    quote_id UUID NOT NULL,
+    version INT NOT NULL,
+    author TEXT NOT NULL,
+    quote TEXT NOT NULL,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (quote_id, version)
+);
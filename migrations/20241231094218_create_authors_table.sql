@@ -0,0 +1,34 @@
+-- Authors are matched on their normalized name, so "Santa" and " santa" are the same person.
+CREATE OR REPLACE FUNCTION clean_author_name(name TEXT) RETURNS TEXT
+    LANGUAGE SQL IMMUTABLE
+    AS $$ SELECT regexp_replace(btrim(name), '\s+', ' ', 'g') $$;
+
+CREATE OR REPLACE FUNCTION normalize_author_name(name TEXT) RETURNS TEXT
+    LANGUAGE SQL IMMUTABLE
+    AS $$ SELECT lower(clean_author_name(name)) $$;
+
+CREATE TABLE IF NOT EXISTS authors (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL CHECK (name <> ''),
+    normalized_name TEXT NOT NULL UNIQUE
+);
+
+-- Quotes from before authors had to be given have nobody to be attributed to otherwise.
+UPDATE quotes SET author = 'Unknown' WHERE clean_author_name(author) = '';
+
+-- The earliest spelling of each author becomes their name, and the one all their quotes show.
+INSERT INTO authors (name, normalized_name)
+SELECT DISTINCT ON (normalize_author_name(author)) clean_author_name(author), normalize_author_name(author)
+FROM quotes
+ORDER BY normalize_author_name(author), created_at ASC
+ON CONFLICT DO NOTHING;
+
+ALTER TABLE quotes ADD COLUMN IF NOT EXISTS author_id UUID REFERENCES authors (id);
+
+UPDATE quotes SET author_id = authors.id, author = authors.name
+FROM authors
+WHERE quotes.author_id IS NULL AND authors.normalized_name = normalize_author_name(quotes.author);
+
+ALTER TABLE quotes ALTER COLUMN author_id SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS quotes_author_id_idx ON quotes (author_id, created_at, id);
@@ -0,0 +1,8 @@
+-- Tags are stored normalized, so "Christmas" and "christmas " are the same tag.
+CREATE TABLE IF NOT EXISTS quote_tags (
+    quote_id UUID NOT NULL REFERENCES quotes (id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (quote_id, tag)
+);
+
+CREATE INDEX IF NOT EXISTS quote_tags_tag_idx ON quote_tags (tag, quote_id);
@@ -0,0 +1,14 @@
+-- Quotes belong to the quote book of their owner.
+-- Quotes without an owner are in the anonymous quote book that everyone shares.
+ALTER TABLE quotes ADD COLUMN IF NOT EXISTS owner TEXT;
+ALTER TABLE quote_versions ADD COLUMN IF NOT EXISTS owner TEXT;
+
+CREATE INDEX IF NOT EXISTS quotes_owner_idx ON quotes (owner, created_at, id);
+
+-- Only hashes of API keys are stored, the keys themselves are shown once when they're created.
+CREATE TABLE IF NOT EXISTS api_keys (
+    key_hash BYTEA PRIMARY KEY,
+    owner TEXT NOT NULL,
+    role TEXT NOT NULL DEFAULT 'owner' CHECK (role IN ('owner', 'admin')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,68 @@
+-- The same rules the API validates quotes with, so that invalid quotes can't get in any other way.
+-- Existing rows aren't checked, `quote_violations` lists those that break any of the rules.
+
+-- Lengths in characters, the way the API counts them. A SQL_ASCII database doesn't know its text is
+-- UTF-8 and counts bytes instead, so there UTF-8 continuation bytes are left out of the count.
+DO $$
+BEGIN
+    IF current_setting('server_encoding') = 'SQL_ASCII' THEN
+        CREATE OR REPLACE FUNCTION quote_text_length(text TEXT) RETURNS INTEGER
+            LANGUAGE SQL IMMUTABLE
+            AS $f$ SELECT char_length(regexp_replace(text, '[\x80-\xBF]', '', 'g')) $f$;
+    ELSE
+        CREATE OR REPLACE FUNCTION quote_text_length(text TEXT) RETURNS INTEGER
+            LANGUAGE SQL IMMUTABLE
+            AS $f$ SELECT char_length(text) $f$;
+    END IF;
+END
+$$;
+
+ALTER TABLE quotes
+    ADD CONSTRAINT quotes_author_length CHECK (quote_text_length(author) BETWEEN 1 AND 100) NOT VALID,
+    ADD CONSTRAINT quotes_quote_length CHECK (quote_text_length(quote) BETWEEN 1 AND 1000) NOT VALID,
+    ADD CONSTRAINT quotes_author_trimmed CHECK (author !~ '^\s|\s$') NOT VALID,
+    ADD CONSTRAINT quotes_quote_trimmed CHECK (quote !~ '^\s|\s$') NOT VALID,
+    ADD CONSTRAINT quotes_author_control_characters CHECK (author !~ '[\x01-\x1F\x7F]') NOT VALID,
+    ADD CONSTRAINT quotes_quote_control_characters CHECK (quote !~ '[\x01-\x08\x0B-\x1F\x7F]') NOT VALID;
+
+-- Normalization and the C1 control characters only make sense if the database knows its text is Unicode.
+DO $$
+BEGIN
+    IF current_setting('server_encoding') = 'UTF8' THEN
+        ALTER TABLE quotes
+            ADD CONSTRAINT quotes_author_normalized CHECK (author IS NFC NORMALIZED) NOT VALID,
+            ADD CONSTRAINT quotes_quote_normalized CHECK (quote IS NFC NORMALIZED) NOT VALID,
+            ADD CONSTRAINT quotes_author_c1_control_characters CHECK (author !~ '[\x80-\x9F]') NOT VALID,
+            ADD CONSTRAINT quotes_quote_c1_control_characters CHECK (quote !~ '[\x80-\x9F]') NOT VALID;
+    END IF;
+END
+$$;
+
+-- Quotes that break the rules above, along with the names of the constraints they violate.
+-- Once it's empty, the constraints can be validated with `ALTER TABLE quotes VALIDATE CONSTRAINT ...`.
+CREATE OR REPLACE VIEW quote_violations AS
+SELECT id, owner, violations
+FROM (
+    SELECT id, owner, array_remove(ARRAY[
+        CASE WHEN quote_text_length(author) NOT BETWEEN 1 AND 100 THEN 'quotes_author_length' END,
+        CASE WHEN quote_text_length(quote) NOT BETWEEN 1 AND 1000 THEN 'quotes_quote_length' END,
+        CASE WHEN author ~ '^\s|\s$' THEN 'quotes_author_trimmed' END,
+        CASE WHEN quote ~ '^\s|\s$' THEN 'quotes_quote_trimmed' END,
+        CASE WHEN author ~ '[\x01-\x1F\x7F]' THEN 'quotes_author_control_characters' END,
+        CASE WHEN quote ~ '[\x01-\x08\x0B-\x1F\x7F]' THEN 'quotes_quote_control_characters' END,
+        CASE WHEN current_setting('server_encoding') = 'UTF8' THEN
+            CASE WHEN NOT author IS NFC NORMALIZED THEN 'quotes_author_normalized' END
+        END,
+        CASE WHEN current_setting('server_encoding') = 'UTF8' THEN
+            CASE WHEN NOT quote IS NFC NORMALIZED THEN 'quotes_quote_normalized' END
+        END,
+        CASE WHEN current_setting('server_encoding') = 'UTF8' THEN
+            CASE WHEN author ~ '[\x80-\x9F]' THEN 'quotes_author_c1_control_characters' END
+        END,
+        CASE WHEN current_setting('server_encoding') = 'UTF8' THEN
+            CASE WHEN quote ~ '[\x80-\x9F]' THEN 'quotes_quote_c1_control_characters' END
+        END
+    ], NULL) AS violations
+    FROM quotes
+) checked
+WHERE cardinality(violations) > 0;
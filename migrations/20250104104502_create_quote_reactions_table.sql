@@ -0,0 +1,11 @@
+-- Reactions to quotes, at most one of each kind per owner and quote. Only authenticated owners
+-- can react, anonymous clients can't be told apart well enough to count them only once.
+CREATE TABLE IF NOT EXISTS quote_reactions (
+    quote_id UUID NOT NULL REFERENCES quotes (id) ON DELETE CASCADE,
+    owner TEXT NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('like', 'favourite')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (quote_id, owner, kind)
+);
+
+CREATE INDEX IF NOT EXISTS quote_reactions_created_at_idx ON quote_reactions (created_at, quote_id);
@@ -1,38 +1,205 @@
 use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    convert::Infallible,
     fmt::{Display, Write},
     sync::Arc,
 };
 
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    async_trait,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{request::Parts, HeaderName, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response, Result,
+    },
     routing::{get, post},
-    Router,
+    Json, Router,
 };
-use rand::{rngs::StdRng, Rng, SeedableRng};
-use tokio::sync::RwLock;
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{types::Uuid, FromRow, PgPool};
+use tokio::{
+    sync::{broadcast, Mutex, OwnedMutexGuard, RwLock},
+    time::{self, Duration, Instant},
+};
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+
+use crate::negotiate;
 
-pub fn day_twelve() -> Router {
+/// How long a game may go without any requests before it is dropped from the registry.
+static GAME_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// How often the registry is checked for idle games.
+static GAME_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/// The largest width or height a board may have.
+static MAX_BOARD_SIZE: usize = 16;
+/// How many moves ahead the computer opponent looks, unless told otherwise.
+static DEFAULT_AI_DEPTH: u32 = 4;
+/// The deepest search the computer opponent may be asked to do.
+static MAX_AI_DEPTH: u32 = 8;
+/// The score of a won position, before adjusting for how soon the win happens.
+static WIN_SCORE: i32 = 1_000_000;
+/// The seed random boards are drawn from after a reset.
+static DEFAULT_SEED: u64 = 2024;
+/// The furthest into a seed's sequence a random board may be requested from.
+static MAX_SEED_OFFSET: u64 = 1 << 24;
+/// The seed a random board was generated from.
+static SEED_HEADER: HeaderName = HeaderName::from_static("x-seed");
+/// How many values had already been drawn from the seed before generating a random board.
+static SEED_OFFSET_HEADER: HeaderName = HeaderName::from_static("x-seed-offset");
+/// The most work a single search by the computer opponent may take, measured in tiles looked at
+/// in the worst case. Deeper searches on wide boards are cut short to stay within it.
+static MAX_SEARCH_WORK: u64 = 1 << 27;
+/// The directions a line can run in: along a row, down a column and along both diagonals.
+const DIRECTIONS: [(isize, isize); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];
+/// How many board events a slow subscriber may fall behind before it starts missing some.
+static EVENT_CAPACITY: usize = 64;
+
+pub fn day_twelve(pool: PgPool) -> Router {
     let state = AppState {
+        pool: Arc::new(pool),
         game: Arc::new(RwLock::new(Game::default())),
-        rng: Arc::new(RwLock::new(StdRng::seed_from_u64(2024))),
+        events: broadcast::channel(EVENT_CAPACITY).0,
+        games: Arc::new(RwLock::new(HashMap::new())),
+        rng: Arc::new(RwLock::new(SeededRng::new(DEFAULT_SEED))),
     };
 
+    tokio::spawn(expire_games(state.games.clone()));
+
     Router::new()
         .route("/12/board", get(board))
         .route("/12/reset", post(reset))
         .route("/12/place/:team/:column", post(place))
         .route("/12/random-board", get(random_board))
+        .route("/12/moves", get(moves))
+        .route("/12/replay", post(replay))
+        .route("/12/load", post(load))
+        .route("/12/events", get(events))
+        .route("/12/undo", post(undo))
+        .route("/12/redo", post(redo))
+        .route("/12/analyze", get(analyze))
+        .route("/12/results", get(results))
+        .route("/12/stats", get(stats))
+        .route("/12/games", get(list_games).post(create_game))
+        .route("/12/games/replay", post(replay_game))
+        .route("/12/games/:id/board", get(game_board))
+        .route("/12/games/:id/reset", post(game_reset))
+        .route("/12/games/:id/place/:team/:column", post(game_place))
+        .route("/12/games/:id/moves", get(game_moves))
+        .route("/12/games/:id/load", post(game_load))
+        .route("/12/games/:id/random-board", get(game_random_board))
+        .route("/12/games/:id/events", get(game_events))
+        .route("/12/games/:id/stats", get(game_stats))
+        .route("/12/games/:id/undo", post(game_undo))
+        .route("/12/games/:id/redo", post(game_redo))
+        .route("/12/games/:id/analyze", get(game_analyze))
         .with_state(state)
 }
 
 #[derive(Clone)]
 struct AppState {
+    /// Where the games under `/12/games` are kept, so they survive restarts.
+    pool: Arc<PgPool>,
+    /// The game played through the plain `/12/...` endpoints.
     game: Arc<RwLock<Game>>,
-    rng: Arc<RwLock<StdRng>>,
+    /// Every change to `game`, for spectators.
+    events: broadcast::Sender<BoardEvent>,
+    /// Additional games, each addressed by its own ID under `/12/games/:id/...`.
+    ///
+    /// Only recently used games are held here, the rest are loaded from the database on demand.
+    /// Each session has a lock of its own, so the registry only has to be locked to find one.
+    games: Arc<RwLock<HashMap<Uuid, Arc<Mutex<Session>>>>>,
+    /// The random sequence of `game`. Sessions have sequences of their own.
+    rng: Arc<RwLock<SeededRng>>,
+}
+
+/// A ChaCha generator that remembers its seed and how far along it is,
+/// so that anything generated with it can be generated again.
+///
+/// It produces the same sequence as a [`StdRng`] with the same seed.
+struct SeededRng {
+    seed: u64,
+    rng: ChaCha12Rng,
+}
+
+impl SeededRng {
+    fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: ChaCha12Rng::seed_from_u64(seed),
+        }
+    }
+
+    /// Seeds a new generator and jumps past the first `offset` words without generating them.
+    fn with_offset(seed: u64, offset: u64) -> Self {
+        let mut rng = Self::new(seed);
+        rng.rng.set_word_pos(offset.into());
+
+        rng
+    }
+
+    /// How many 32-bit words have been drawn since seeding.
+    fn offset(&self) -> u64 {
+        self.rng.get_word_pos() as u64
+    }
+}
+
+impl RngCore for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+struct Session {
+    game: Game,
+    /// Every change to `game`, for spectators.
+    events: broadcast::Sender<BoardEvent>,
+    /// Random boards and AI tie-breaks for this game, so other games can't shift its sequence.
+    rng: SeededRng,
+    last_active: Instant,
+}
+
+impl Session {
+    fn new(id: Uuid, game: Game) -> Self {
+        Self {
+            game,
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            rng: SeededRng::new(Self::seed(id)),
+            last_active: Instant::now(),
+        }
+    }
+
+    /// The seed of the game's random sequence, derived from its ID.
+    ///
+    /// The sequence isn't stored, so it starts over when the game is loaded from the database.
+    fn seed(id: Uuid) -> u64 {
+        let (high, low) = id.as_u64_pair();
+        high ^ low
+    }
+
+    fn touch(&mut self) {
+        self.last_active = Instant::now();
+    }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
 enum Tile {
     Empty,
     Cookie,
@@ -57,13 +224,180 @@ impl Display for Tile {
     }
 }
 
-#[derive(Default)]
+impl Tile {
+    /// Returns the other team, or `Empty` for an empty tile.
+    fn opponent(self) -> Self {
+        match self {
+            Tile::Empty => Tile::Empty,
+            Tile::Cookie => Tile::Milk,
+            Tile::Milk => Tile::Cookie,
+        }
+    }
+}
+
+/// The dimensions of a board, how many tiles in a row it takes to win,
+/// whether the teams have to take turns and who plays the other team.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct Rules {
+    width: usize,
+    height: usize,
+    win_length: usize,
+    strict: bool,
+    opponent: Opponent,
+    /// How many moves ahead the computer opponent looks.
+    depth: u32,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            width: 4,
+            height: 4,
+            win_length: 4,
+            strict: false,
+            opponent: Opponent::Human,
+            depth: DEFAULT_AI_DEPTH,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+enum Opponent {
+    /// Both teams are played through the `place` endpoints.
+    Human,
+    /// The server answers every move with one for the other team.
+    Ai,
+}
+
+impl Rules {
+    /// Makes sure the rules describe a playable board.
+    fn validate(self) -> Result<Self, StatusCode> {
+        if !(1..=MAX_BOARD_SIZE).contains(&self.width)
+            || !(1..=MAX_BOARD_SIZE).contains(&self.height)
+            || self.win_length < 2
+            || self.win_length > self.width.max(self.height)
+            || !(1..=MAX_AI_DEPTH).contains(&self.depth)
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        Ok(self)
+    }
+}
+
+/// Optional overrides for [`Rules`], taken from the query string.
+#[derive(Deserialize, Default)]
+struct RulesParams {
+    width: Option<usize>,
+    height: Option<usize>,
+    win_length: Option<usize>,
+    strict: Option<bool>,
+    opponent: Option<Opponent>,
+    depth: Option<u32>,
+}
+
+impl RulesParams {
+    /// The rules asked for, with the defaults filling in whatever isn't given.
+    ///
+    /// Nothing carries over from the board being replaced, so a bare reset is back to 4x4.
+    fn rules(self) -> Result<Rules, StatusCode> {
+        let rules = Rules::default();
+        Rules {
+            width: self.width.unwrap_or(rules.width),
+            height: self.height.unwrap_or(rules.height),
+            win_length: self.win_length.unwrap_or(rules.win_length),
+            strict: self.strict.unwrap_or(rules.strict),
+            opponent: self.opponent.unwrap_or(rules.opponent),
+            depth: self.depth.unwrap_or(rules.depth),
+        }
+        .validate()
+    }
+}
+
+/// Per-request overrides for who plays the other team.
+#[derive(Deserialize, Default)]
+struct OpponentParams {
+    opponent: Option<Opponent>,
+    depth: Option<u32>,
+}
+
+impl OpponentParams {
+    /// Returns the search depth if the computer should answer moves in a game with `rules`.
+    fn ai_depth(self, rules: Rules) -> Result<Option<u32>, StatusCode> {
+        let depth = self.depth.unwrap_or(rules.depth);
+        if !(1..=MAX_AI_DEPTH).contains(&depth) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        match self.opponent.unwrap_or(rules.opponent) {
+            Opponent::Human => Ok(None),
+            Opponent::Ai => Ok(Some(depth)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AnalyzeParams {
+    /// How many moves ahead to look for a forced win.
+    depth: Option<u32>,
+}
+
+impl AnalyzeParams {
+    fn depth(&self) -> Result<u32, StatusCode> {
+        let depth = self.depth.unwrap_or(DEFAULT_AI_DEPTH);
+        if !(1..=MAX_AI_DEPTH).contains(&depth) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        Ok(depth)
+    }
+}
+
+/// A single placement, as made through one of the `place` endpoints.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Move {
+    team: Tile,
+    /// The column the tile was dropped into, starting at 1.
+    column: usize,
+}
+
+/// Everything needed to rebuild a game from scratch.
+#[derive(Serialize, Deserialize)]
+struct MoveLog {
+    #[serde(flatten)]
+    rules: Rules,
+    /// The position the moves were played from, if the board didn't start out empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    position: Option<String>,
+    moves: Vec<Move>,
+}
+
+#[derive(Clone)]
 struct Game {
-    board: [Tile; 4 * 4],
+    rules: Rules,
+    /// The tiles in row-major order, starting with the top row.
+    board: Vec<Tile>,
     status: GameStatus,
+    /// The board as it was before the first of `moves`.
+    start: Vec<Tile>,
+    /// Every move made since the board was last reset, oldest first.
+    moves: Vec<Move>,
+    /// Moves taken back with undo, most recently undone last.
+    undone: Vec<Move>,
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new(Rules::default())
+    }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Eq, PartialEq, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
 enum GameStatus {
     CookieWins,
     MilkWins,
@@ -77,24 +411,32 @@ impl Default for GameStatus {
     }
 }
 
+/// Draws the board as emoji art.
+///
+/// The alternate flag (`{:#}`) replaces the tiles of the winning line with stars.
 impl Display for Game {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut output = String::new();
+        let line = if f.alternate() {
+            self.winning_line()
+                .map(|(_, line)| line)
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
 
-        for row in 0..5 {
-            if row == 4 {
-                writeln!(output, "⬜⬜⬜⬜⬜⬜")?;
-            } else {
-                writeln!(
-                    output,
-                    "⬜{}{}{}{}⬜",
-                    self.board[row * 4],
-                    self.board[row * 4 + 1],
-                    self.board[row * 4 + 2],
-                    self.board[row * 4 + 3]
-                )?;
+        for (r, row) in self.board.chunks_exact(self.rules.width).enumerate() {
+            output.push('⬜');
+            for (c, tile) in row.iter().enumerate() {
+                if line.contains(&[r, c]) {
+                    output.push('⭐');
+                } else {
+                    write!(output, "{tile}")?;
+                }
             }
+            writeln!(output, "⬜")?;
         }
+        writeln!(output, "{}", "⬜".repeat(self.rules.width + 2))?;
 
         match self.status {
             GameStatus::CookieWins => writeln!(output, "🍪 wins!")?,
@@ -108,184 +450,1860 @@ impl Display for Game {
 }
 
 impl Game {
-    // TODO: make this more efficient??
-    fn update_status(&mut self) -> GameStatus {
-        // Check rows
-        for row in self.board.as_slice().chunks_exact(4) {
-            if row.iter().all(|&t| t == Tile::Cookie) {
-                self.status = GameStatus::CookieWins;
-
-                return GameStatus::CookieWins;
-            } else if row.iter().all(|&t| t == Tile::Milk) {
-                self.status = GameStatus::MilkWins;
+    fn new(rules: Rules) -> Self {
+        let board = vec![Tile::Empty; rules.width * rules.height];
 
-                return GameStatus::MilkWins;
-            }
+        Self {
+            rules,
+            start: board.clone(),
+            board,
+            status: GameStatus::InProgress,
+            moves: Vec::new(),
+            undone: Vec::new(),
         }
+    }
 
-        // Check columns
-        for n in 0..4 {
-            if self.board[n..]
-                .iter()
-                .step_by(4)
-                .take(4)
-                .all(|&t| t == Tile::Cookie)
-            {
-                self.status = GameStatus::CookieWins;
-
-                return GameStatus::CookieWins;
-            } else if self.board[n..]
-                .iter()
-                .step_by(4)
-                .take(4)
-                .all(|&t| t == Tile::Milk)
-            {
-                self.status = GameStatus::MilkWins;
-
-                return GameStatus::MilkWins;
-            }
+    /// Sets up a game from a position as produced by [`Game::position`].
+    ///
+    /// The width and height of `rules` are replaced by those of the position.
+    fn from_position(rules: Rules, position: &str) -> Result<Self, StatusCode> {
+        let (width, board) = parse_position(position).ok_or(StatusCode::BAD_REQUEST)?;
+        let rules = Rules {
+            width,
+            height: board.len() / width,
+            ..rules
         }
+        .validate()?;
 
-        // Check diagonals
-        if self
-            .board
+        // Every tile has to rest on the bottom or on another tile.
+        let floating = board
             .iter()
-            .step_by(5)
-            .take(4)
-            .all(|&t| t == Tile::Cookie)
-        {
-            self.status = GameStatus::CookieWins;
+            .zip(&board[width..])
+            .any(|(&above, &below)| above != Tile::Empty && below == Tile::Empty);
+        if floating {
+            return Err(StatusCode::BAD_REQUEST);
+        }
 
-            return GameStatus::CookieWins;
-        } else if self
-            .board
-            .iter()
-            .step_by(5)
-            .take(4)
-            .all(|&t| t == Tile::Milk)
-        {
-            self.status = GameStatus::MilkWins;
+        let mut game = Self {
+            rules,
+            start: board.clone(),
+            board,
+            status: GameStatus::InProgress,
+            moves: Vec::new(),
+            undone: Vec::new(),
+        };
+        game.update_status();
+
+        Ok(game)
+    }
 
-            return GameStatus::MilkWins;
+    /// Plays all `moves` in order.
+    ///
+    /// On failure, returns the index of the first move that couldn't be played.
+    fn replay(&mut self, moves: &[Move]) -> Result<(), usize> {
+        for (i, m) in moves.iter().enumerate() {
+            let col = m.column.checked_sub(1).ok_or(i)?;
+            self.place(m.team, col).map_err(|_| i)?;
         }
-        if self
-            .board
+
+        Ok(())
+    }
+
+    fn log(&self) -> MoveLog {
+        let position = self
+            .start
             .iter()
-            .skip(3)
-            .step_by(3)
-            .take(4)
-            .all(|&t| t == Tile::Cookie)
-        {
-            self.status = GameStatus::CookieWins;
+            .any(|&t| t != Tile::Empty)
+            .then(|| encode_position(&self.start, self.rules.width));
 
-            return GameStatus::CookieWins;
+        MoveLog {
+            rules: self.rules,
+            position,
+            moves: self.moves.clone(),
+        }
+    }
+
+    /// Encodes the board as rows from top to bottom, separated by `/`.
+    ///
+    /// `c` and `m` stand for cookie and milk tiles, numbers for runs of empty tiles,
+    /// so an empty 4x4 board with a single cookie in the bottom left is `4/4/4/c3`.
+    fn position(&self) -> String {
+        encode_position(&self.board, self.rules.width)
+    }
+
+    /// Returns the team whose turn it is.
+    ///
+    /// This follows from the last move, or if there is none, from which team has fewer tiles.
+    /// If both teams have the same number of tiles, either one may go.
+    fn next_team(&self) -> Option<Tile> {
+        if let Some(m) = self.moves.last() {
+            return Some(m.team.opponent());
+        }
+
+        let cookies = self.board.iter().filter(|&&t| t == Tile::Cookie).count();
+        let milks = self.board.iter().filter(|&&t| t == Tile::Milk).count();
+        match cookies.cmp(&milks) {
+            Ordering::Less => Some(Tile::Cookie),
+            Ordering::Greater => Some(Tile::Milk),
+            Ordering::Equal => None,
+        }
+    }
+
+    /// Returns the tile at `row` and `col`, or `None` if that is off the board.
+    fn tile(&self, row: isize, col: isize) -> Option<Tile> {
+        let (width, height) = (self.rules.width as isize, self.rules.height as isize);
+        if !(0..height).contains(&row) || !(0..width).contains(&col) {
+            return None;
         }
-        if self
-            .board
-            .iter()
-            .skip(3)
-            .step_by(3)
-            .take(4)
-            .all(|&t| t == Tile::Milk)
-        {
-            self.status = GameStatus::MilkWins;
 
-            return GameStatus::MilkWins;
+        Some(self.board[(row * width + col) as usize])
+    }
+
+    /// Looks for `win_length` tiles of the same team in a row,
+    /// returning the team and the `[row, column]` of every tile in the line.
+    ///
+    /// Rows are checked first, then columns, then both diagonals,
+    /// each scanning the board from the top left.
+    fn winning_line(&self) -> Option<(Tile, Vec<[usize; 2]>)> {
+        let (width, height) = (self.rules.width as isize, self.rules.height as isize);
+        let len = self.rules.win_length as isize;
+
+        for (dr, dc) in DIRECTIONS {
+            for row in 0..height {
+                for col in 0..width {
+                    let tile = self.tile(row, col);
+                    if tile.is_none_or(|t| t == Tile::Empty) {
+                        continue;
+                    }
+
+                    if (1..len).all(|i| self.tile(row + dr * i, col + dc * i) == tile) {
+                        let line = (0..len)
+                            .map(|i| [(row + dr * i) as usize, (col + dc * i) as usize])
+                            .collect();
+
+                        return tile.map(|t| (t, line));
+                    }
+                }
+            }
         }
 
-        // Check if board is full
-        if !self.board.iter().any(|&t| t == Tile::Empty) {
-            self.status = GameStatus::NoWinner;
+        None
+    }
 
-            return GameStatus::NoWinner;
+    fn update_status(&mut self) -> GameStatus {
+        match self.winning_line().map(|(team, _)| team) {
+            Some(Tile::Cookie) => self.status = GameStatus::CookieWins,
+            Some(Tile::Milk) => self.status = GameStatus::MilkWins,
+            _ if !self.board.contains(&Tile::Empty) => self.status = GameStatus::NoWinner,
+            _ => {}
         }
 
-        GameStatus::InProgress
+        self.status
     }
 
-    fn random(rng: &mut StdRng) -> Self {
-        let mut board = [Tile::Empty; 4 * 4];
+    /// Fills every tile of the board with a coin flip.
+    fn random(rules: Rules, rng: &mut impl Rng) -> Self {
+        let mut game = Self::new(rules);
 
-        for tile in board.iter_mut() {
+        for tile in game.board.iter_mut() {
             if rng.gen::<bool>() {
                 *tile = Tile::Cookie;
             } else {
                 *tile = Tile::Milk;
             }
         }
+        game.start = game.board.clone();
+        game.update_status();
 
-        let mut game = Self {
-            board,
-            status: GameStatus::InProgress,
+        game
+    }
+
+    /// Plays a random number of random moves, with the teams taking turns.
+    ///
+    /// Unlike [`Game::random`], the result could come up in a real game,
+    /// and it stops as soon as one team wins.
+    fn random_legal(rules: Rules, rng: &mut impl Rng) -> Self {
+        let mut game = Self::new(rules);
+        let mut team = if rng.gen::<bool>() {
+            Tile::Cookie
+        } else {
+            Tile::Milk
         };
-        game.update_status();
+
+        for _ in 0..rng.gen_range(0..=game.board.len()) {
+            if game.status != GameStatus::InProgress {
+                break;
+            }
+
+            let open: Vec<usize> = (0..rules.width)
+                .filter(|&col| game.board[col] == Tile::Empty)
+                .collect();
+            let col = open[rng.gen_range(0..open.len())];
+            // The column has space and the teams alternate, so this can't fail.
+            let _ = game.place(team, col);
+            team = team.opponent();
+        }
 
         game
     }
-}
 
-async fn board(State(state): State<AppState>) -> (StatusCode, String) {
-    (StatusCode::OK, state.game.read().await.to_string())
+    /// Drops a tile of `team` into the zero-indexed column `col`.
+    fn place(&mut self, team: Tile, col: usize) -> Result<(), PlaceError> {
+        if team == Tile::Empty || col >= self.rules.width {
+            return Err(PlaceError::InvalidColumn);
+        }
+        if self.board[col] != Tile::Empty {
+            return Err(PlaceError::ColumnFull);
+        }
+        if self.status != GameStatus::InProgress {
+            return Err(PlaceError::GameOver);
+        }
+        if self.rules.strict && self.next_team().is_some_and(|next| next != team) {
+            return Err(PlaceError::OutOfTurn);
+        }
+
+        let row = self
+            .drop_row(col)
+            .expect("column should have an empty slot");
+        self.board[row * self.rules.width + col] = team;
+        self.moves.push(Move {
+            team,
+            column: col + 1,
+        });
+        self.update_status();
+
+        Ok(())
+    }
+
+    /// Takes back the last move, returning it.
+    fn undo(&mut self) -> Option<Move> {
+        let m = self.moves.pop()?;
+
+        // The last tile dropped into a column is always the topmost one.
+        let col = m.column - 1;
+        let row = (0..self.rules.height)
+            .find(|row| self.board[row * self.rules.width + col] != Tile::Empty)
+            .expect("column of a played move should have a tile");
+        self.board[row * self.rules.width + col] = Tile::Empty;
+
+        self.status = GameStatus::InProgress;
+        self.update_status();
+        self.undone.push(m);
+
+        Some(m)
+    }
+
+    /// Plays the most recently undone move again, returning it.
+    fn redo(&mut self) -> Option<Move> {
+        let m = self.undone.pop()?;
+        self.place(m.team, m.column - 1)
+            .expect("undone move should be playable again");
+
+        Some(m)
+    }
+
+    /// Copies the board without its history, for trying out moves.
+    fn scratch(&self) -> Self {
+        Self {
+            rules: self.rules,
+            board: self.board.clone(),
+            status: self.status,
+            start: Vec::new(),
+            moves: Vec::new(),
+            undone: Vec::new(),
+        }
+    }
+
+    /// Returns the row a tile dropped into `col` would land in, or `None` if the column is full.
+    fn drop_row(&self, col: usize) -> Option<usize> {
+        (0..self.rules.height)
+            .rev()
+            .find(|row| self.board[row * self.rules.width + col] == Tile::Empty)
+    }
+
+    /// Checks whether the tile at `row` and `col` is part of a winning line.
+    ///
+    /// Unlike [`Game::winning_line`], this only looks at lines through a single tile,
+    /// which is all that can change with one move.
+    fn wins_through(&self, row: usize, col: usize) -> bool {
+        let (row, col) = (row as isize, col as isize);
+        let tile = self.tile(row, col);
+        if tile.is_none_or(|t| t == Tile::Empty) {
+            return false;
+        }
+
+        DIRECTIONS.iter().any(|&(dr, dc)| {
+            let count = |sign: isize| {
+                (1..)
+                    .take_while(|i| self.tile(row + sign * dr * i, col + sign * dc * i) == tile)
+                    .count()
+            };
+
+            1 + count(1) + count(-1) >= self.rules.win_length
+        })
+    }
 }
 
-async fn reset(State(state): State<AppState>) -> (StatusCode, String) {
-    let mut board = state.game.write().await;
-    *board = Game::default();
-    let mut rng = state.rng.write().await;
-    *rng = StdRng::seed_from_u64(2024);
+/// How deep a search on a board with `rules` may go, at most `depth`.
+///
+/// Every level multiplies the number of positions by up to the width of the board,
+/// and each position reached at the end is evaluated by looking at every possible line.
+fn search_depth(rules: Rules, depth: u32) -> u32 {
+    let evaluation = 4 * (rules.width * rules.height * rules.win_length) as u64;
 
-    (StatusCode::OK, board.to_string())
+    (1..=depth)
+        .take_while(|&depth| {
+            (rules.width as u64)
+                .checked_pow(depth)
+                .and_then(|positions| positions.checked_mul(evaluation))
+                .is_some_and(|work| work <= MAX_SEARCH_WORK)
+        })
+        .last()
+        .unwrap_or(1)
 }
 
-async fn place(
-    State(state): State<AppState>,
-    Path((team, col)): Path<(String, usize)>,
-) -> (StatusCode, String) {
-    if !matches!(team.as_str(), "cookie" | "milk") {
-        return (StatusCode::BAD_REQUEST, "".to_owned());
+/// Picks a column for `team` to play, using a minimax search with alpha-beta pruning
+/// that looks `depth` moves ahead, or fewer if the board is too big for that, see [`search_depth`].
+///
+/// If several columns are equally good, `rng` decides between them, so a seeded `rng`
+/// always gives the same answer for the same position.
+/// Returns `None` if every column is full.
+fn best_move(game: &Game, team: Tile, depth: u32, rng: &mut impl Rng) -> Option<usize> {
+    let mut game = game.scratch();
+    let depth = search_depth(game.rules, depth);
+
+    let mut best = Vec::new();
+    let mut best_score = i32::MIN;
+
+    for col in 0..game.rules.width {
+        let Some(row) = game.drop_row(col) else {
+            continue;
+        };
+
+        let slot = row * game.rules.width + col;
+        game.board[slot] = team;
+        let score = if game.wins_through(row, col) {
+            WIN_SCORE + depth as i32
+        } else {
+            -negamax(&mut game, team.opponent(), depth - 1, -i32::MAX, i32::MAX)
+        };
+        game.board[slot] = Tile::Empty;
+
+        if score > best_score {
+            best_score = score;
+            best.clear();
+        }
+        if score == best_score {
+            best.push(col);
+        }
     }
-    if !(1..=4).contains(&col) {
-        return (StatusCode::BAD_REQUEST, "".to_owned());
+
+    if best.is_empty() {
+        return None;
     }
 
-    let mut game = state.game.write().await;
+    Some(best[rng.gen_range(0..best.len())])
+}
+
+/// Scores the position from the point of view of `team`, which is about to move.
+fn negamax(game: &mut Game, team: Tile, depth: u32, mut alpha: i32, beta: i32) -> i32 {
+    if depth == 0 {
+        return evaluate(game, team);
+    }
+
+    let width = game.rules.width;
+    // Columns near the middle tend to be better, so trying them first prunes more.
+    let mut columns: Vec<usize> = (0..width).collect();
+    columns.sort_by_key(|&col| (2 * col).abs_diff(width - 1));
+
+    let mut best = None;
+    for col in columns {
+        let Some(row) = game.drop_row(col) else {
+            continue;
+        };
+
+        let slot = row * width + col;
+        game.board[slot] = team;
+        let score = if game.wins_through(row, col) {
+            WIN_SCORE + depth as i32
+        } else {
+            -negamax(game, team.opponent(), depth - 1, -beta, -alpha)
+        };
+        game.board[slot] = Tile::Empty;
 
-    if game.board[col - 1] != Tile::Empty {
-        return (StatusCode::SERVICE_UNAVAILABLE, format!("{game}"));
+        best = Some(best.map_or(score, |best: i32| best.max(score)));
+        alpha = alpha.max(score);
+        if alpha >= beta {
+            break;
+        }
     }
 
-    if game.status != GameStatus::InProgress {
-        return (StatusCode::SERVICE_UNAVAILABLE, format!("{game}"));
+    // A full board without a winner is a draw.
+    best.unwrap_or(0)
+}
+
+/// Estimates how good the position is for `team` by looking at every possible winning line.
+///
+/// Lines that only contain tiles of one team count in that team's favour,
+/// the more tiles, the more so.
+fn evaluate(game: &Game, team: Tile) -> i32 {
+    let (width, height) = (game.rules.width as isize, game.rules.height as isize);
+    let len = game.rules.win_length as isize;
+    let mut score = 0;
+
+    for (dr, dc) in DIRECTIONS {
+        for row in 0..height {
+            for col in 0..width {
+                if game
+                    .tile(row + dr * (len - 1), col + dc * (len - 1))
+                    .is_none()
+                {
+                    continue;
+                }
+
+                let (mut own, mut other) = (0, 0);
+                for i in 0..len {
+                    match game.tile(row + dr * i, col + dc * i) {
+                        Some(t) if t == team => own += 1,
+                        Some(Tile::Empty) | None => {}
+                        Some(_) => other += 1,
+                    }
+                }
+
+                match (own, other) {
+                    (own, 0) => score += own * own,
+                    (0, other) => score -= other * other,
+                    _ => {}
+                }
+            }
+        }
     }
 
-    let team = if team == "cookie" {
-        Tile::Cookie
+    score
+}
+
+/// The team that wins with best play from both sides within `depth` moves,
+/// if `team` is the one to move.
+fn forced_winner(game: &Game, team: Tile, depth: u32) -> Option<Tile> {
+    let score = negamax(&mut game.scratch(), team, depth, -i32::MAX, i32::MAX);
+
+    if score >= WIN_SCORE {
+        Some(team)
+    } else if score <= -WIN_SCORE {
+        Some(team.opponent())
     } else {
-        Tile::Milk
-    };
+        None
+    }
+}
+
+/// Columns, counted from 1 like in the `place` endpoints.
+#[derive(Serialize, Default)]
+struct TeamColumns {
+    cookie: Vec<usize>,
+    milk: Vec<usize>,
+}
+
+impl TeamColumns {
+    fn get_mut(&mut self, team: Tile) -> &mut Vec<usize> {
+        match team {
+            Tile::Milk => &mut self.milk,
+            _ => &mut self.cookie,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Analysis {
+    status: GameStatus,
+    next_to_move: Option<Tile>,
+    /// Columns that still have space, counted from 1.
+    playable: Vec<usize>,
+    /// Columns where a team wins right away.
+    winning: TeamColumns,
+    /// Columns where a team doesn't win, but lets the other team win with its next move.
+    losing: TeamColumns,
+    /// The team that can force a win within `depth` moves, whoever goes next.
+    forced_win: Option<Tile>,
+    depth: u32,
+}
 
-    for row in 0..4 {
-        let slot = col - 1 + 4 * row;
-        if game.board[slot] == Tile::Empty {
-            game.board[slot] = team;
+impl Analysis {
+    /// Analyses `game`, looking `depth` moves ahead for a forced win,
+    /// or fewer if the board is too big for that, see [`search_depth`].
+    fn new(game: &Game, depth: u32) -> Self {
+        let depth = search_depth(game.rules, depth);
+        let mut analysis = Self {
+            status: game.status,
+            next_to_move: None,
+            playable: Vec::new(),
+            winning: TeamColumns::default(),
+            losing: TeamColumns::default(),
+            forced_win: match game.status {
+                GameStatus::CookieWins => Some(Tile::Cookie),
+                GameStatus::MilkWins => Some(Tile::Milk),
+                _ => None,
+            },
+            depth,
+        };
+        if game.status != GameStatus::InProgress {
+            return analysis;
+        }
+
+        analysis.next_to_move = game.next_team();
+        let width = game.rules.width;
+        let mut scratch = game.scratch();
+
+        for col in 0..width {
+            let Some(row) = scratch.drop_row(col) else {
+                continue;
+            };
+            analysis.playable.push(col + 1);
 
-            if row != 0 {
-                game.board[col - 1 + 4 * (row - 1)] = Tile::Empty;
+            for team in [Tile::Cookie, Tile::Milk] {
+                let slot = row * width + col;
+                scratch.board[slot] = team;
+
+                if scratch.wins_through(row, col) {
+                    analysis.winning.get_mut(team).push(col + 1);
+                } else if (0..width).any(|reply| {
+                    scratch.drop_row(reply).is_some_and(|reply_row| {
+                        let reply_slot = reply_row * width + reply;
+                        scratch.board[reply_slot] = team.opponent();
+                        let wins = scratch.wins_through(reply_row, reply);
+                        scratch.board[reply_slot] = Tile::Empty;
+
+                        wins
+                    })
+                }) {
+                    analysis.losing.get_mut(team).push(col + 1);
+                }
+
+                scratch.board[slot] = Tile::Empty;
             }
-        } else {
-            break;
         }
+
+        analysis.forced_win = match analysis.next_to_move {
+            Some(team) => forced_winner(game, team, depth),
+            // If either team may go, a win is only forced if it doesn't matter who does.
+            None => forced_winner(game, Tile::Cookie, depth)
+                .filter(|&winner| forced_winner(game, Tile::Milk, depth) == Some(winner)),
+        };
+
+        analysis
     }
-    game.update_status();
 
-    (StatusCode::OK, format!("{game}"))
+    /// Runs [`Analysis::new`] on a blocking thread, so the search doesn't hold up the rest of the server.
+    async fn spawn(game: Game, depth: u32) -> Self {
+        tokio::task::spawn_blocking(move || Self::new(&game, depth))
+            .await
+            .expect("analysis should not panic")
+    }
 }
 
-async fn random_board(State(state): State<AppState>) -> String {
-    let mut game = state.game.write().await;
-    let mut rng = state.rng.write().await;
+fn encode_position(board: &[Tile], width: usize) -> String {
+    let mut position = String::new();
+
+    for (i, row) in board.chunks_exact(width).enumerate() {
+        if i != 0 {
+            position.push('/');
+        }
+
+        let mut empty = 0;
+        for tile in row {
+            let c = match tile {
+                Tile::Empty => {
+                    empty += 1;
+                    continue;
+                }
+                Tile::Cookie => 'c',
+                Tile::Milk => 'm',
+            };
+            if empty != 0 {
+                position.push_str(&empty.to_string());
+                empty = 0;
+            }
+            position.push(c);
+        }
+        if empty != 0 {
+            position.push_str(&empty.to_string());
+        }
+    }
+
+    position
+}
 
-    *game = Game::random(&mut rng);
+/// Parses a position as produced by [`encode_position`], returning the width and the tiles.
+fn parse_position(position: &str) -> Option<(usize, Vec<Tile>)> {
+    let mut board = Vec::new();
+    let mut width = None;
+
+    for row in position.trim().split('/') {
+        let len = board.len();
+        let mut empty = String::new();
+
+        for c in row.chars().chain(std::iter::once('/')) {
+            if c.is_ascii_digit() {
+                empty.push(c);
+                continue;
+            }
+            if !empty.is_empty() {
+                let n: usize = empty.parse().ok().filter(|&n| n <= MAX_BOARD_SIZE)?;
+                board.extend(std::iter::repeat(Tile::Empty).take(n));
+                empty.clear();
+            }
+
+            match c {
+                'c' => board.push(Tile::Cookie),
+                'm' => board.push(Tile::Milk),
+                '/' => {}
+                _ => return None,
+            }
+        }
+
+        let row_width = board.len() - len;
+        if row_width == 0 || *width.get_or_insert(row_width) != row_width {
+            return None;
+        }
+    }
+
+    Some((width?, board))
+}
+
+#[derive(Debug)]
+enum PlaceError {
+    InvalidColumn,
+    ColumnFull,
+    GameOver,
+    OutOfTurn,
+}
+
+/// How a board is sent back, picked from the request's `Accept` header.
+#[derive(Clone, Copy)]
+enum Format {
+    /// The emoji art from [`Game`]'s `Display` impl,
+    /// with the winning line marked if `highlight` is set.
+    Text { highlight: bool },
+    /// A [`BoardView`].
+    Json,
+}
 
-    format!("{game}")
+#[derive(Deserialize)]
+struct FormatParams {
+    #[serde(default)]
+    highlight: bool,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Format
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // Plain text stays the default, JSON has to be preferred over it.
+        let preferred = negotiate::preferred(&parts.headers, &["text/plain", "application/json"]);
+        if preferred == Some("application/json") {
+            return Ok(Self::Json);
+        }
+
+        let highlight =
+            Query::<FormatParams>::try_from_uri(&parts.uri).is_ok_and(|params| params.highlight);
+
+        Ok(Self::Text { highlight })
+    }
+}
+
+impl Format {
+    fn text(self, game: &Game) -> String {
+        match self {
+            Format::Text { highlight: true } => format!("{game:#}"),
+            _ => game.to_string(),
+        }
+    }
+
+    fn render(self, status: StatusCode, game: &Game) -> Response {
+        match self {
+            Format::Text { .. } => (status, self.text(game)).into_response(),
+            Format::Json => (status, Json(BoardView::new(game))).into_response(),
+        }
+    }
+
+    /// Renders an error `message`, as a line of text or as a JSON object with an `error` field.
+    fn error(self, status: StatusCode, message: String) -> Response {
+        match self {
+            Format::Text { .. } => (status, format!("{message}\n")).into_response(),
+            Format::Json => (status, Json(json!({ "error": message }))).into_response(),
+        }
+    }
+
+    fn event(self, event: &BoardEvent) -> Event {
+        let data = match self {
+            Format::Text { .. } => self.text(&event.game).trim_end().to_owned(),
+            Format::Json => serde_json::to_string(&BoardView::new(&event.game))
+                .expect("board should serialize to JSON"),
+        };
+
+        Event::default().event(event.kind).data(data)
+    }
+}
+
+/// A change to a board, as sent to spectators.
+#[derive(Clone)]
+struct BoardEvent {
+    /// What happened, e.g. `place` or `reset`.
+    kind: &'static str,
+    /// The board after the change.
+    game: Arc<Game>,
+}
+
+impl BoardEvent {
+    fn new(kind: &'static str, game: &Game) -> Self {
+        Self {
+            kind,
+            game: Arc::new(game.clone()),
+        }
+    }
+}
+
+/// Tells all subscribers of `events` about a change to `game`.
+fn publish(events: &broadcast::Sender<BoardEvent>, kind: &'static str, game: &Game) {
+    // Sending only fails if nobody is watching, which is fine.
+    let _ = events.send(BoardEvent::new(kind, game));
+}
+
+/// Streams the current board, followed by every change to it, as server-sent events.
+fn subscribe(
+    events: &broadcast::Sender<BoardEvent>,
+    game: &Game,
+    format: Format,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let current = BoardEvent::new("board", game);
+    let changes = BroadcastStream::new(events.subscribe())
+        // A lagging subscriber just misses a few events, the next one has the whole board anyway.
+        .filter_map(|event| event.ok());
+
+    let stream = tokio_stream::once(current)
+        .chain(changes)
+        .map(move |event| Ok(format.event(&event)));
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// The board in a form bots don't have to scrape.
+#[derive(Serialize)]
+struct BoardView {
+    /// The rows of the board, starting with the top one.
+    tiles: Vec<Vec<Tile>>,
+    status: GameStatus,
+    next_to_move: Option<Tile>,
+    /// The `[row, column]` of every tile in the winning line, counted from 0 at the top left.
+    winning_line: Option<Vec<[usize; 2]>>,
+    position: String,
+}
+
+impl BoardView {
+    fn new(game: &Game) -> Self {
+        let in_progress = game.status == GameStatus::InProgress;
+
+        Self {
+            tiles: game
+                .board
+                .chunks_exact(game.rules.width)
+                .map(<[Tile]>::to_vec)
+                .collect(),
+            status: game.status,
+            next_to_move: game.next_team().filter(|_| in_progress),
+            winning_line: game.winning_line().map(|(_, line)| line),
+            position: game.position(),
+        }
+    }
+}
+
+/// Handles a placement request from one of the `place` endpoints.
+///
+/// If `ai` is given, the computer answers a successful move with its own, searching with the
+/// given depth and breaking ties with a seed drawn from the given rng. The search runs on a
+/// blocking thread, so it doesn't hold up the rest of the server.
+async fn place_tile(
+    game: &mut Game,
+    team: &str,
+    col: usize,
+    ai: Option<(u32, &mut SeededRng)>,
+    format: Format,
+) -> Response {
+    let team = match team {
+        "cookie" => Tile::Cookie,
+        "milk" => Tile::Milk,
+        _ => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    if col == 0 {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    match game.place(team, col - 1) {
+        Ok(()) => {
+            // A new move replaces whatever could have been redone.
+            game.undone.clear();
+
+            if let Some((depth, rng)) = ai {
+                if game.status == GameStatus::InProgress {
+                    let seed = rng.next_u64();
+                    let position = game.scratch();
+                    let reply = tokio::task::spawn_blocking(move || {
+                        best_move(
+                            &position,
+                            team.opponent(),
+                            depth,
+                            &mut StdRng::seed_from_u64(seed),
+                        )
+                    })
+                    .await
+                    .expect("search should not panic");
+
+                    if let Some(col) = reply {
+                        // The column is known to have space and it's the other team's turn.
+                        let _ = game.place(team.opponent(), col);
+                    }
+                }
+            }
+
+            format.render(StatusCode::OK, game)
+        }
+        Err(PlaceError::InvalidColumn) => StatusCode::BAD_REQUEST.into_response(),
+        Err(PlaceError::ColumnFull | PlaceError::GameOver) => {
+            format.render(StatusCode::SERVICE_UNAVAILABLE, game)
+        }
+        Err(PlaceError::OutOfTurn) => format.error(
+            StatusCode::CONFLICT,
+            format!("It's {}'s turn!", team.opponent()),
+        ),
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct RandomParams {
+    /// Generate the board from this seed instead of continuing the game's sequence.
+    seed: Option<u64>,
+    /// Skip this many values of `seed` first, e.g. to get a board from the game's sequence again.
+    offset: Option<u64>,
+    /// Only generate boards that can come up in a real game.
+    #[serde(default)]
+    legal: bool,
+}
+
+impl RandomParams {
+    /// The generator for the requested seed, if any, already at the requested offset.
+    ///
+    /// Jumping ahead is cheap, but it's still done before the game is locked.
+    fn seeded(&self) -> Result<Option<SeededRng>, StatusCode> {
+        match (self.seed, self.offset) {
+            (Some(seed), offset) => {
+                let offset = offset.unwrap_or(0);
+                if offset > MAX_SEED_OFFSET {
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+
+                Ok(Some(SeededRng::with_offset(seed, offset)))
+            }
+            (None, Some(_)) => Err(StatusCode::BAD_REQUEST),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// Generates a random board with `rules` from `rng`.
+///
+/// Returns the board along with the seed and offset it was generated from.
+fn generate_board(rules: Rules, legal: bool, rng: &mut SeededRng) -> (Game, u64, u64) {
+    let (seed, offset) = (rng.seed, rng.offset());
+    let game = if legal {
+        Game::random_legal(rules, rng)
+    } else {
+        Game::random(rules, rng)
+    };
+
+    (game, seed, offset)
+}
+
+/// Renders a random board, along with headers telling how to get the same board again.
+fn render_random(format: Format, game: &Game, seed: u64, offset: u64) -> Response {
+    let headers = [
+        (SEED_HEADER.clone(), seed.to_string()),
+        (SEED_OFFSET_HEADER.clone(), offset.to_string()),
+    ];
+
+    (headers, format.render(StatusCode::OK, game)).into_response()
+}
+
+/// Replaces `game` with the one described by `log`.
+fn load_log(game: &mut Game, log: MoveLog) -> Result<()> {
+    let rules = log.rules.validate()?;
+    let mut replayed = match log.position {
+        Some(position) => Game::from_position(rules, &position)?,
+        None => Game::new(rules),
+    };
+
+    replayed.replay(&log.moves).map_err(|i| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Move {} can't be played\n", i + 1),
+        )
+    })?;
+    *game = replayed;
+
+    Ok(())
+}
+
+async fn board(State(state): State<AppState>, format: Format) -> Response {
+    let game = state.game.read().await;
+
+    format.render(StatusCode::OK, &game)
+}
+
+async fn reset(
+    State(state): State<AppState>,
+    Query(params): Query<RulesParams>,
+    format: Format,
+) -> Result<Response> {
+    let mut board = state.game.write().await;
+    *board = Game::new(params.rules()?);
+    let mut rng = state.rng.write().await;
+    *rng = SeededRng::new(DEFAULT_SEED);
+    publish(&state.events, "reset", &board);
+
+    Ok(format.render(StatusCode::OK, &board))
+}
+
+async fn place(
+    State(state): State<AppState>,
+    Path((team, col)): Path<(String, usize)>,
+    Query(params): Query<OpponentParams>,
+    format: Format,
+) -> Result<Response> {
+    let mut game = state.game.write().await;
+    let ai = params.ai_depth(game.rules)?;
+    let mut rng = state.rng.write().await;
+
+    let response = place_tile(
+        &mut game,
+        &team,
+        col,
+        ai.map(|depth| (depth, &mut *rng)),
+        format,
+    )
+    .await;
+    if response.status() == StatusCode::OK {
+        publish(&state.events, "place", &game);
+    }
+
+    Ok(response)
+}
+
+async fn moves(State(state): State<AppState>) -> Json<MoveLog> {
+    Json(state.game.read().await.log())
+}
+
+async fn replay(
+    State(state): State<AppState>,
+    format: Format,
+    Json(log): Json<MoveLog>,
+) -> Result<Response> {
+    let mut game = state.game.write().await;
+    load_log(&mut game, log)?;
+    publish(&state.events, "replay", &game);
+
+    Ok(format.render(StatusCode::OK, &game))
+}
+
+async fn load(
+    State(state): State<AppState>,
+    Query(params): Query<RulesParams>,
+    format: Format,
+    position: String,
+) -> Result<Response> {
+    let mut game = state.game.write().await;
+    *game = Game::from_position(params.rules()?, &position)?;
+    publish(&state.events, "load", &game);
+
+    Ok(format.render(StatusCode::OK, &game))
+}
+
+async fn random_board(
+    State(state): State<AppState>,
+    Query(params): Query<RandomParams>,
+    format: Format,
+) -> Result<Response> {
+    let seeded = params.seeded()?;
+    let mut game = state.game.write().await;
+
+    let (random, seed, offset) = match seeded {
+        Some(mut rng) => generate_board(game.rules, params.legal, &mut rng),
+        None => generate_board(game.rules, params.legal, &mut *state.rng.write().await),
+    };
+    *game = random;
+    publish(&state.events, "random-board", &game);
+
+    Ok(render_random(format, &game, seed, offset))
+}
+
+async fn events(
+    State(state): State<AppState>,
+    format: Format,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let game = state.game.read().await;
+
+    subscribe(&state.events, &game, format)
+}
+
+async fn undo(State(state): State<AppState>, format: Format) -> Response {
+    let mut game = state.game.write().await;
+
+    if game.undo().is_none() {
+        return format.render(StatusCode::CONFLICT, &game);
+    }
+    publish(&state.events, "undo", &game);
+
+    format.render(StatusCode::OK, &game)
+}
+
+async fn redo(State(state): State<AppState>, format: Format) -> Response {
+    let mut game = state.game.write().await;
+
+    if game.redo().is_none() {
+        return format.render(StatusCode::CONFLICT, &game);
+    }
+    publish(&state.events, "redo", &game);
+
+    format.render(StatusCode::OK, &game)
+}
+
+async fn analyze(
+    State(state): State<AppState>,
+    Query(params): Query<AnalyzeParams>,
+) -> Result<Json<Analysis>> {
+    let depth = params.depth()?;
+    let game = state.game.read().await.clone();
+
+    Ok(Json(Analysis::spawn(game, depth).await))
+}
+
+#[derive(FromRow)]
+struct GameRow {
+    width: i32,
+    height: i32,
+    win_length: i32,
+    strict: bool,
+    opponent: Opponent,
+    depth: i32,
+    position: Option<String>,
+}
+
+#[derive(FromRow)]
+struct MoveRow {
+    team: Tile,
+    col: i32,
+}
+
+/// Stores `game` under `id`, replacing whatever was stored before.
+///
+/// `new_round` tells whether `game` starts over rather than continuing the stored game,
+/// which restarts the clock for how long it has been going.
+async fn save_game(
+    pool: &PgPool,
+    id: Uuid,
+    game: &Game,
+    new_round: bool,
+) -> Result<(), sqlx::Error> {
+    let log = game.log();
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        "INSERT INTO connect_four_games (id, width, height, win_length, strict, opponent, depth, position, status, finished_at)
+        VALUES (
+            $1, $2, $3, $4, $5, $6, $7, $8, $9,
+            CASE WHEN $9 = 'in_progress' THEN NULL ELSE CURRENT_TIMESTAMP END
+        )
+        ON CONFLICT (id) DO UPDATE SET
+            width = EXCLUDED.width,
+            height = EXCLUDED.height,
+            win_length = EXCLUDED.win_length,
+            strict = EXCLUDED.strict,
+            opponent = EXCLUDED.opponent,
+            depth = EXCLUDED.depth,
+            position = EXCLUDED.position,
+            status = EXCLUDED.status,
+            updated_at = CURRENT_TIMESTAMP,
+            started_at = CASE
+                WHEN $10 THEN CURRENT_TIMESTAMP
+                ELSE connect_four_games.started_at
+            END,
+            finished_at = CASE
+                WHEN $10 OR EXCLUDED.finished_at IS NULL THEN EXCLUDED.finished_at
+                ELSE COALESCE(connect_four_games.finished_at, EXCLUDED.finished_at)
+            END",
+    )
+    .bind(id)
+    .bind(log.rules.width as i32)
+    .bind(log.rules.height as i32)
+    .bind(log.rules.win_length as i32)
+    .bind(log.rules.strict)
+    .bind(log.rules.opponent)
+    .bind(log.rules.depth as i32)
+    .bind(log.position)
+    .bind(game.status)
+    .bind(new_round)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM connect_four_moves WHERE game_id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    for (seq, m) in log.moves.iter().enumerate() {
+        sqlx::query(
+            "INSERT INTO connect_four_moves (game_id, seq, team, col) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(id)
+        .bind(seq as i32)
+        .bind(m.team)
+        .bind(m.column as i32)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await
+}
+
+/// Rebuilds the game stored under `id` by replaying its moves.
+async fn load_game(pool: &PgPool, id: Uuid) -> Result<Option<Game>, sqlx::Error> {
+    let row: Option<GameRow> = sqlx::query_as("SELECT * FROM connect_four_games WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+
+    let moves: Vec<MoveRow> =
+        sqlx::query_as("SELECT * FROM connect_four_moves WHERE game_id = $1 ORDER BY seq ASC")
+            .bind(id)
+            .fetch_all(pool)
+            .await?;
+
+    let log = MoveLog {
+        rules: Rules {
+            width: row.width as usize,
+            height: row.height as usize,
+            win_length: row.win_length as usize,
+            strict: row.strict,
+            opponent: row.opponent,
+            depth: row.depth as u32,
+        },
+        position: row.position,
+        moves: moves
+            .into_iter()
+            .map(|m| Move {
+                team: m.team,
+                column: m.col as usize,
+            })
+            .collect(),
+    };
+
+    let mut game = Game::default();
+    load_log(&mut game, log)
+        .map_err(|_| sqlx::Error::Decode("stored game can't be replayed".into()))?;
+
+    Ok(Some(game))
+}
+
+/// Looks up the session for `id`, loading the game from the database if it isn't in memory,
+/// and locks it. The registry itself is only locked for the lookup, never while waiting on the
+/// database, so requests for different games don't hold each other up.
+async fn session(state: &AppState, id: Uuid) -> Result<OwnedMutexGuard<Session>> {
+    let existing = state.games.read().await.get(&id).cloned();
+    let session = match existing {
+        Some(session) => session,
+        None => {
+            let game = load_game(&state.pool, id)
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .ok_or(StatusCode::NOT_FOUND)?;
+
+            // Another request may have loaded the game in the meantime, in which case that one wins.
+            state
+                .games
+                .write()
+                .await
+                .entry(id)
+                .or_insert_with(|| Arc::new(Mutex::new(Session::new(id, game))))
+                .clone()
+        }
+    };
+
+    let mut session = session.lock_owned().await;
+    session.touch();
+
+    Ok(session)
+}
+
+#[derive(Serialize)]
+struct GameInfo {
+    id: Uuid,
+    #[serde(flatten)]
+    rules: Rules,
+    status: GameStatus,
+    idle_secs: u64,
+}
+
+impl GameInfo {
+    fn new(id: Uuid, session: &Session) -> Self {
+        Self {
+            id,
+            rules: session.game.rules,
+            status: session.game.status,
+            idle_secs: session.last_active.elapsed().as_secs(),
+        }
+    }
+}
+
+/// Registers a new game and stores it in the database.
+async fn insert_game(state: &AppState, game: Game) -> Result<(StatusCode, Json<GameInfo>)> {
+    let id = Uuid::new_v4();
+    save_game(&state.pool, id, &game, true)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let session = Session::new(id, game);
+    let info = GameInfo::new(id, &session);
+    state
+        .games
+        .write()
+        .await
+        .insert(id, Arc::new(Mutex::new(session)));
+
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+async fn create_game(
+    State(state): State<AppState>,
+    Query(params): Query<RulesParams>,
+) -> Result<(StatusCode, Json<GameInfo>)> {
+    let rules = params.rules()?;
+
+    insert_game(&state, Game::new(rules)).await
+}
+
+async fn replay_game(
+    State(state): State<AppState>,
+    Json(log): Json<MoveLog>,
+) -> Result<(StatusCode, Json<GameInfo>)> {
+    let mut game = Game::default();
+    load_log(&mut game, log)?;
+
+    insert_game(&state, game).await
+}
+
+async fn list_games(State(state): State<AppState>) -> Json<Vec<GameInfo>> {
+    // Sessions may be busy for a while, so the registry isn't kept locked while waiting for them.
+    let sessions: Vec<(Uuid, Arc<Mutex<Session>>)> = state
+        .games
+        .read()
+        .await
+        .iter()
+        .map(|(&id, session)| (id, session.clone()))
+        .collect();
+
+    let mut infos = Vec::with_capacity(sessions.len());
+    for (id, session) in sessions {
+        infos.push(GameInfo::new(id, &*session.lock().await));
+    }
+    infos.sort_by_key(|info| info.idle_secs);
+
+    Json(infos)
+}
+
+/// Counts all stored games by their status, e.g. how often each team has won.
+async fn results(State(state): State<AppState>) -> Result<Json<HashMap<String, i64>>> {
+    let counts: Vec<(String, i64)> =
+        sqlx::query_as("SELECT status, COUNT(*) FROM connect_four_games GROUP BY status")
+            .fetch_all(&*state.pool)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(counts.into_iter().collect()))
+}
+
+/// How often games ended a certain way, and how they got there.
+#[derive(Serialize, FromRow)]
+struct OutcomeStats {
+    status: GameStatus,
+    games: i64,
+    /// The fraction of all finished games that ended this way.
+    share: f64,
+    avg_moves: f64,
+    min_moves: i64,
+    max_moves: i64,
+    /// Seconds from the start of a round until it was decided.
+    avg_secs_to_finish: f64,
+}
+
+#[derive(Serialize)]
+struct Stats {
+    finished: i64,
+    outcomes: Vec<OutcomeStats>,
+}
+
+async fn stats(State(state): State<AppState>) -> Result<Json<Stats>> {
+    let outcomes: Vec<OutcomeStats> = sqlx::query_as(
+        "SELECT
+            status,
+            COUNT(*) AS games,
+            COUNT(*)::FLOAT8 / SUM(COUNT(*)) OVER () AS share,
+            AVG(moves)::FLOAT8 AS avg_moves,
+            MIN(moves) AS min_moves,
+            MAX(moves) AS max_moves,
+            AVG(EXTRACT(EPOCH FROM finished_at - started_at))::FLOAT8 AS avg_secs_to_finish
+        FROM (
+            SELECT g.*, (SELECT COUNT(*) FROM connect_four_moves m WHERE m.game_id = g.id) AS moves
+            FROM connect_four_games g
+            WHERE finished_at IS NOT NULL
+        ) finished
+        GROUP BY status
+        ORDER BY games DESC",
+    )
+    .fetch_all(&*state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(Stats {
+        finished: outcomes.iter().map(|o| o.games).sum(),
+        outcomes,
+    }))
+}
+
+#[derive(Serialize, FromRow)]
+struct GameStats {
+    status: GameStatus,
+    moves: i64,
+    cookie_moves: i64,
+    milk_moves: i64,
+    /// Seconds since the start of the current round, or until it was decided if it's over.
+    secs_played: f64,
+    /// Seconds from the start of the current round until it was decided, if it's over.
+    secs_to_finish: Option<f64>,
+}
+
+async fn game_stats(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<GameStats>> {
+    let stats: GameStats = sqlx::query_as(
+        "SELECT
+            g.status,
+            COUNT(m.seq) AS moves,
+            COUNT(m.seq) FILTER (WHERE m.team = 'cookie') AS cookie_moves,
+            COUNT(m.seq) FILTER (WHERE m.team = 'milk') AS milk_moves,
+            EXTRACT(EPOCH FROM COALESCE(g.finished_at, CURRENT_TIMESTAMP) - g.started_at)::FLOAT8 AS secs_played,
+            EXTRACT(EPOCH FROM g.finished_at - g.started_at)::FLOAT8 AS secs_to_finish
+        FROM connect_four_games g
+        LEFT JOIN connect_four_moves m ON m.game_id = g.id
+        WHERE g.id = $1
+        GROUP BY g.id",
+    )
+    .bind(id)
+    .fetch_one(&*state.pool)
+    .await
+    .map_err(|e| {
+        if matches!(e, sqlx::Error::RowNotFound) {
+            StatusCode::NOT_FOUND
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })?;
+
+    Ok(Json(stats))
+}
+
+async fn game_board(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    format: Format,
+) -> Result<Response> {
+    let session = session(&state, id).await?;
+
+    Ok(format.render(StatusCode::OK, &session.game))
+}
+
+/// Stores `game` as the new state of the session's game and tells spectators about it.
+///
+/// The game is only replaced once it has been saved, so a failed save leaves the session as it was.
+/// `new_round` is passed on to [`save_game`].
+async fn commit(
+    state: &AppState,
+    id: Uuid,
+    session: &mut Session,
+    game: Game,
+    kind: &'static str,
+    new_round: bool,
+) -> Result<()> {
+    save_game(&state.pool, id, &game, new_round)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    session.game = game;
+    publish(&session.events, kind, &session.game);
+
+    Ok(())
+}
+
+async fn game_reset(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<RulesParams>,
+    format: Format,
+) -> Result<Response> {
+    let mut session = session(&state, id).await?;
+    let game = Game::new(params.rules()?);
+    commit(&state, id, &mut session, game, "reset", true).await?;
+    session.rng = SeededRng::new(Session::seed(id));
+
+    Ok(format.render(StatusCode::OK, &session.game))
+}
+
+async fn game_place(
+    State(state): State<AppState>,
+    Path((id, team, col)): Path<(Uuid, String, usize)>,
+    Query(params): Query<OpponentParams>,
+    format: Format,
+) -> Result<Response> {
+    let mut session = session(&state, id).await?;
+    let ai = params.ai_depth(session.game.rules)?;
+
+    let mut game = session.game.clone();
+    let response = place_tile(
+        &mut game,
+        &team,
+        col,
+        ai.map(|depth| (depth, &mut session.rng)),
+        format,
+    )
+    .await;
+    if response.status() == StatusCode::OK {
+        commit(&state, id, &mut session, game, "place", false).await?;
+    }
+
+    Ok(response)
+}
+
+async fn game_moves(State(state): State<AppState>, Path(id): Path<Uuid>) -> Result<Json<MoveLog>> {
+    let session = session(&state, id).await?;
+
+    Ok(Json(session.game.log()))
+}
+
+async fn game_load(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<RulesParams>,
+    format: Format,
+    position: String,
+) -> Result<Response> {
+    let mut session = session(&state, id).await?;
+    let game = Game::from_position(params.rules()?, &position)?;
+    commit(&state, id, &mut session, game, "load", true).await?;
+
+    Ok(format.render(StatusCode::OK, &session.game))
+}
+
+async fn game_random_board(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<RandomParams>,
+    format: Format,
+) -> Result<Response> {
+    let seeded = params.seeded()?;
+    let mut session = session(&state, id).await?;
+
+    let (game, seed, offset) = match seeded {
+        Some(mut rng) => generate_board(session.game.rules, params.legal, &mut rng),
+        None => generate_board(session.game.rules, params.legal, &mut session.rng),
+    };
+    commit(&state, id, &mut session, game, "random-board", true).await?;
+
+    Ok(render_random(format, &session.game, seed, offset))
+}
+
+async fn game_undo(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    format: Format,
+) -> Result<Response> {
+    let mut session = session(&state, id).await?;
+
+    let mut game = session.game.clone();
+    if game.undo().is_none() {
+        return Ok(format.render(StatusCode::CONFLICT, &session.game));
+    }
+    commit(&state, id, &mut session, game, "undo", false).await?;
+
+    Ok(format.render(StatusCode::OK, &session.game))
+}
+
+async fn game_redo(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    format: Format,
+) -> Result<Response> {
+    let mut session = session(&state, id).await?;
+
+    let mut game = session.game.clone();
+    if game.redo().is_none() {
+        return Ok(format.render(StatusCode::CONFLICT, &session.game));
+    }
+    commit(&state, id, &mut session, game, "redo", false).await?;
+
+    Ok(format.render(StatusCode::OK, &session.game))
+}
+
+async fn game_analyze(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<AnalyzeParams>,
+) -> Result<Json<Analysis>> {
+    let depth = params.depth()?;
+    let game = session(&state, id).await?.game.clone();
+
+    Ok(Json(Analysis::spawn(game, depth).await))
+}
+
+async fn game_events(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    format: Format,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    let session = session(&state, id).await?;
+
+    Ok(subscribe(&session.events, &session.game, format))
+}
+
+/// Periodically removes games that haven't been touched for [`GAME_IDLE_TIMEOUT`].
+///
+/// They stay in the database and are loaded again the next time they're asked for.
+async fn expire_games(games: Arc<RwLock<HashMap<Uuid, Arc<Mutex<Session>>>>>) {
+    let mut interval = time::interval(GAME_SWEEP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        // Sessions are only handed out while the registry is locked, so one that nobody else
+        // holds on to right now can't be in use and is safe to drop.
+        games.write().await.retain(|_, session| {
+            Arc::strong_count(session) > 1
+                || session.try_lock().map_or(true, |session| {
+                    session.last_active.elapsed() < GAME_IDLE_TIMEOUT
+                })
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup(rules: Rules, position: &str) -> Game {
+        Game::from_position(rules, position).unwrap_or_else(|_| panic!("bad position {position}"))
+    }
+
+    /// State for handlers that never touch the database.
+    fn state() -> AppState {
+        AppState {
+            pool: Arc::new(PgPool::connect_lazy("postgres://localhost/unused").unwrap()),
+            game: Arc::new(RwLock::new(Game::default())),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            games: Arc::new(RwLock::new(HashMap::new())),
+            rng: Arc::new(RwLock::new(SeededRng::new(DEFAULT_SEED))),
+        }
+    }
+
+    fn params(query: &str) -> RulesParams {
+        let uri = format!("/12/reset?{query}").parse().unwrap();
+        Query::try_from_uri(&uri).unwrap().0
+    }
+
+    #[test]
+    fn winning_line_in_every_direction() {
+        let rules = Rules::default();
+
+        let (team, line) = setup(rules, "4/4/4/cccc").winning_line().unwrap();
+        assert!(team == Tile::Cookie);
+        assert_eq!(line, [[3, 0], [3, 1], [3, 2], [3, 3]]);
+
+        let (team, line) = setup(rules, "m3/m3/m3/mccc").winning_line().unwrap();
+        assert!(team == Tile::Milk);
+        assert_eq!(line, [[0, 0], [1, 0], [2, 0], [3, 0]]);
+
+        let (team, line) = setup(rules, "3c/2cm/1cmm/cmmm").winning_line().unwrap();
+        assert!(team == Tile::Cookie);
+        assert_eq!(line, [[0, 3], [1, 2], [2, 1], [3, 0]]);
+
+        assert!(setup(rules, "4/4/4/4").winning_line().is_none());
+        assert!(setup(rules, "4/4/4/cmcm").winning_line().is_none());
+    }
+
+    #[test]
+    fn best_move_takes_an_immediate_win() {
+        let game = setup(Rules::default(), "4/4/mm2/ccc1");
+
+        let col = best_move(
+            &game,
+            Tile::Cookie,
+            DEFAULT_AI_DEPTH,
+            &mut StdRng::seed_from_u64(1),
+        );
+        assert_eq!(col, Some(3));
+    }
+
+    #[test]
+    fn best_move_blocks_a_forced_loss() {
+        let game = setup(Rules::default(), "4/4/c3/mmm1");
+
+        for seed in 0..8 {
+            let col = best_move(
+                &game,
+                Tile::Cookie,
+                DEFAULT_AI_DEPTH,
+                &mut StdRng::seed_from_u64(seed),
+            );
+            assert_eq!(col, Some(3));
+        }
+    }
+
+    #[test]
+    fn best_move_is_the_same_for_the_same_seed() {
+        let rules = Rules {
+            width: 7,
+            height: 6,
+            ..Rules::default()
+        };
+        let game = Game::new(rules);
+
+        for seed in 0..8 {
+            let first = best_move(&game, Tile::Cookie, 2, &mut StdRng::seed_from_u64(seed));
+            let second = best_move(&game, Tile::Cookie, 2, &mut StdRng::seed_from_u64(seed));
+            assert!(first.is_some());
+            assert_eq!(first, second);
+        }
+    }
+
+    #[test]
+    fn best_move_on_a_full_board() {
+        let game = setup(Rules::default(), "cmcm/mcmc/cmcm/mcmc");
+
+        assert_eq!(
+            best_move(&game, Tile::Cookie, 1, &mut StdRng::seed_from_u64(0)),
+            None
+        );
+    }
+
+    #[test]
+    fn negamax_sees_wins_and_losses_coming() {
+        let rules = Rules {
+            width: 5,
+            win_length: 3,
+            ..Rules::default()
+        };
+
+        // Cookie can finish the row on either end.
+        let mut game = setup(rules, "5/5/5/1cc1m");
+        assert!(negamax(&mut game, Tile::Cookie, 1, -i32::MAX, i32::MAX) >= WIN_SCORE);
+
+        // Milk threatens both ends, cookie can only block one of them.
+        let mut game = setup(rules, "5/5/5/1mm1c");
+        assert!(negamax(&mut game, Tile::Cookie, 2, -i32::MAX, i32::MAX) <= -WIN_SCORE);
+    }
+
+    #[test]
+    fn analysis_finds_forced_wins_within_its_depth() {
+        let rules = Rules {
+            width: 5,
+            win_length: 3,
+            ..Rules::default()
+        };
+        let analysis = Analysis::new(&setup(rules, "5/5/5/1mm1c"), DEFAULT_AI_DEPTH);
+        assert!(analysis.forced_win == Some(Tile::Milk));
+        assert_eq!(analysis.depth, DEFAULT_AI_DEPTH);
+
+        let large = Rules {
+            width: MAX_BOARD_SIZE,
+            height: MAX_BOARD_SIZE,
+            ..Rules::default()
+        };
+        let analysis = Analysis::new(&Game::new(large), MAX_AI_DEPTH);
+        assert!(analysis.forced_win.is_none());
+        assert_eq!(analysis.depth, search_depth(large, MAX_AI_DEPTH));
+    }
+
+    #[tokio::test]
+    async fn format_follows_the_accept_header() {
+        async fn format(accept: Option<&str>) -> Format {
+            let mut request = axum::http::Request::builder().uri("/12/board?highlight=true");
+            if let Some(accept) = accept {
+                request = request.header("Accept", accept);
+            }
+            let (mut parts, ()) = request.body(()).unwrap().into_parts();
+
+            let Ok(format) = Format::from_request_parts(&mut parts, &()).await;
+            format
+        }
+
+        for accept in [
+            "application/json",
+            "text/plain;q=0.5, application/json",
+            "application/json, text/plain",
+        ] {
+            assert!(
+                matches!(format(Some(accept)).await, Format::Json),
+                "{accept}"
+            );
+        }
+        for accept in [
+            None,
+            Some("*/*"),
+            Some("text/plain, application/json"),
+            Some("text/plain, application/json;q=0.9"),
+            Some("application/json;q=0, */*"),
+        ] {
+            assert!(
+                matches!(format(accept).await, Format::Text { highlight: true }),
+                "{accept:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn search_depth_shrinks_with_the_board() {
+        let small = Rules::default();
+        assert_eq!(search_depth(small, MAX_AI_DEPTH), MAX_AI_DEPTH);
+
+        let large = Rules {
+            width: MAX_BOARD_SIZE,
+            height: MAX_BOARD_SIZE,
+            ..Rules::default()
+        };
+        assert!(search_depth(large, MAX_AI_DEPTH) < MAX_AI_DEPTH);
+        assert!(search_depth(large, 1) == 1);
+    }
+
+    #[tokio::test]
+    async fn reset_goes_back_to_the_default_rules() {
+        let state = state();
+        let format = Format::Text { highlight: false };
+
+        reset(
+            State(state.clone()),
+            Query(params("width=7&height=6&strict=true")),
+            format,
+        )
+        .await
+        .unwrap();
+        assert_eq!(state.game.read().await.rules.width, 7);
+
+        reset(State(state.clone()), Query(params("")), format)
+            .await
+            .unwrap();
+        assert!(state.game.read().await.rules == Rules::default());
+    }
+
+    #[test]
+    fn jumping_to_an_offset_matches_drawing_up_to_it() {
+        let mut drawn = SeededRng::new(DEFAULT_SEED);
+        drawn.next_u32();
+        drawn.next_u64();
+        drawn.fill_bytes(&mut [0; 7]);
+        for _ in 0..100 {
+            drawn.gen_range(0..3);
+        }
+        assert_eq!(drawn.seed, DEFAULT_SEED);
+
+        let mut jumped = SeededRng::with_offset(DEFAULT_SEED, drawn.offset());
+        assert_eq!(jumped.offset(), drawn.offset());
+        assert_eq!(jumped.next_u64(), drawn.next_u64());
+
+        let mut plain = StdRng::seed_from_u64(DEFAULT_SEED);
+        assert_eq!(SeededRng::new(DEFAULT_SEED).next_u64(), plain.next_u64());
+    }
+
+    #[tokio::test]
+    async fn games_draw_from_their_own_sequences() {
+        let rules = Rules::default();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut first = Session::new(a, Game::new(rules));
+        let mut second = Session::new(b, Game::new(rules));
+        let mut alone = Session::new(b, Game::new(rules));
+
+        // Random boards and computer moves in the first game, interleaved with the second.
+        let (board, seed, _) = generate_board(rules, false, &mut second.rng);
+        assert_eq!(seed, Session::seed(b));
+        first.game = generate_board(rules, false, &mut first.rng).0;
+        place_tile(
+            &mut first.game,
+            "cookie",
+            1,
+            Some((1, &mut first.rng)),
+            Format::Json,
+        )
+        .await;
+        place_tile(
+            &mut second.game,
+            "cookie",
+            1,
+            Some((1, &mut second.rng)),
+            Format::Json,
+        )
+        .await;
+        generate_board(rules, true, &mut first.rng);
+
+        // The second game went exactly as it would have on its own.
+        let (alone_board, _, _) = generate_board(rules, false, &mut alone.rng);
+        assert_eq!(alone_board.to_string(), board.to_string());
+        place_tile(
+            &mut alone.game,
+            "cookie",
+            1,
+            Some((1, &mut alone.rng)),
+            Format::Json,
+        )
+        .await;
+        assert_eq!(alone.game.to_string(), second.game.to_string());
+        assert_eq!(alone.rng.offset(), second.rng.offset());
+        assert!(first.rng.offset() > second.rng.offset());
+    }
+
+    #[tokio::test]
+    async fn loading_a_position_drops_the_previous_rules() {
+        let state = state();
+        let format = Format::Json;
+
+        let custom = params("win_length=3&strict=true&opponent=ai&depth=2");
+        load(
+            State(state.clone()),
+            Query(custom),
+            format,
+            "4/4/4/4".into(),
+        )
+        .await
+        .unwrap();
+        assert!(state.game.read().await.rules.strict);
+
+        load(
+            State(state.clone()),
+            Query(params("")),
+            format,
+            "4/4/4/c3".into(),
+        )
+        .await
+        .unwrap();
+        assert!(state.game.read().await.rules == Rules::default());
+    }
 }
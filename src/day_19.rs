@@ -1,37 +1,148 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    response::Result,
+    async_trait,
+    body::{Body, Bytes},
+    extract::{FromRequestParts, Path, Query, State},
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    response::{IntoResponse, Response, Result},
     routing::{delete, get, post, put},
     Json, Router,
 };
+use hmac::{Hmac, Mac};
+use jsonwebtoken as jwt;
 use rand::{distributions::Alphanumeric, Rng};
-use serde::{Deserialize, Serialize};
+use reqwest::{redirect, Url};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
 use sqlx::{
     query_as,
     types::{
-        chrono::{DateTime, Utc},
+        chrono::{DateTime, NaiveDate, Utc},
         Uuid,
     },
-    Executor, FromRow, PgPool,
+    Executor, FromRow, PgPool, Postgres, Transaction,
+};
+use tokio::{
+    net::lookup_host,
+    sync::{mpsc, Notify},
+    time::{self, Duration},
 };
-use tokio::sync::Mutex;
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use tracing::{event, Level};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::negotiate;
+
+/// Quotes per `/19/list` page, unless the first request asks for a different `page_size`.
+static DEFAULT_PAGE_SIZE: i64 = 3;
+static MAX_PAGE_SIZE: i64 = 100;
+/// How long `/19/list` tokens stay valid, unless `QUOTE_CURSOR_TTL_SECS` says otherwise.
+static DEFAULT_CURSOR_TTL: Duration = Duration::from_secs(60 * 60);
+/// How long removed quotes stay in the trash, unless `QUOTE_TRASH_RETENTION_SECS` says otherwise.
+static DEFAULT_TRASH_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// How often the trash is checked for quotes that have been in there for too long.
+static TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Longest author name, in characters, matching the `CHECK` constraints on `quotes`.
+static MAX_AUTHOR_LENGTH: usize = 100;
+/// Longest quote, in characters, matching the `CHECK` constraints on `quotes`.
+static MAX_QUOTE_LENGTH: usize = 1000;
+/// Header for authenticating with an API key from `/19/keys`.
+static API_KEY_HEADER: &str = "X-Api-Key";
+/// How long bearer tokens from `/19/token` stay valid, unless `QUOTE_TOKEN_TTL_SECS` says otherwise.
+static DEFAULT_TOKEN_TTL: Duration = Duration::from_secs(60 * 60);
+/// `iss` of the bearer tokens this service issues.
+static TOKEN_ISSUER: &str = "cch24/19";
+/// `aud` of the bearer tokens this service issues, so tokens meant for anything else don't pass.
+static TOKEN_AUDIENCE: &str = "cch24/19/quotes";
+/// Longest time window `/19/top` ranks quotes over.
+static MAX_TOP_WINDOW: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+/// How often due webhook deliveries are looked for, on top of whenever there's a new one.
+static WEBHOOK_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How long webhook receivers get to respond.
+static WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Delay before the first retry of a failed webhook delivery, which doubles with every retry.
+static WEBHOOK_RETRY_DELAY: Duration = Duration::from_secs(2);
+static MAX_WEBHOOK_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+/// Attempts after which a webhook delivery is given up on.
+static MAX_WEBHOOK_ATTEMPTS: i32 = 8;
+/// How many webhook deliveries are picked up at once.
+static WEBHOOK_BATCH_SIZE: i64 = 16;
 
 pub fn day_nineteen(pool: PgPool) -> Router {
     let state = AppState {
         pool: Arc::new(pool),
-        pagination: Arc::new(Mutex::new(HashMap::new())),
+        cursor_ttl: std::env::var("QUOTE_CURSOR_TTL_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_CURSOR_TTL),
+        cursor_secret: secret("QUOTE_CURSOR_SECRET"),
+        token_secret: secret("QUOTE_TOKEN_SECRET"),
+        token_ttl: std::env::var("QUOTE_TOKEN_TTL_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TOKEN_TTL),
+        webhooks: Arc::new(Notify::new()),
+        allow_private_webhooks: std::env::var("QUOTE_WEBHOOK_ALLOW_PRIVATE_URLS")
+            .ok()
+            .and_then(|allow| allow.parse().ok())
+            .unwrap_or(false),
     };
 
+    let retention = std::env::var("QUOTE_TRASH_RETENTION_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_TRASH_RETENTION);
+    tokio::spawn(purge_trash(state.pool.clone(), retention));
+    tokio::spawn(deliver_webhooks(
+        state.pool.clone(),
+        state.webhooks.clone(),
+        state.allow_private_webhooks,
+    ));
+
+    // There has to be an admin to begin with, who can then hand out keys to everyone else.
+    if let Ok(key) = std::env::var("QUOTE_ADMIN_KEY") {
+        tokio::spawn(register_admin_key(state.pool.clone(), key));
+    }
+
     Router::new()
         .route("/19/reset", post(reset))
         .route("/19/cite/:id", get(cite))
+        .route("/19/cite/:id/history", get(history))
+        .route("/19/cite/:id/tags", get(tags).post(add_tags))
+        .route("/19/cite/:id/tags/:tag", delete(remove_tag))
+        .route("/19/cite/:id/like", post(like).delete(unlike))
+        .route("/19/top", get(top))
         .route("/19/remove/:id", delete(remove))
         .route("/19/undo/:id", put(undo))
         .route("/19/draft", post(draft))
         .route("/19/list", get(list))
+        .route("/19/search", get(search))
+        .route("/19/random", get(random))
+        .route("/19/daily", get(daily))
+        .route("/19/trash", get(trash))
+        .route("/19/restore/:id", post(restore))
+        .route("/19/authors", get(authors))
+        .route("/19/authors/:id", put(rename_author))
+        .route("/19/authors/:id/merge", post(merge_author))
+        .route("/19/authors/:id/quotes", get(author_quotes))
+        .route("/19/import", post(import))
+        .route("/19/export", get(export))
+        .route("/19/keys", post(create_key))
+        .route("/19/token", post(create_token))
+        .route("/19/audit", get(audit))
+        .route("/19/webhooks", get(webhooks).post(subscribe))
+        .route("/19/webhooks/:id", delete(unsubscribe))
+        .route("/19/webhooks/:id/deliveries", get(deliveries))
         .with_state(state)
 }
 
@@ -39,6 +150,7 @@ pub fn day_nineteen(pool: PgPool) -> Router {
 struct Quote {
     id: Uuid,
     author: String,
+    author_id: Uuid,
     quote: String,
     created_at: DateTime<Utc>,
     version: i32,
@@ -47,28 +159,477 @@ struct Quote {
 #[derive(Clone)]
 struct AppState {
     pool: Arc<PgPool>,
-    pagination: Arc<Mutex<HashMap<String, (Uuid, usize)>>>,
+    /// How long `/19/list` tokens stay valid.
+    cursor_ttl: Duration,
+    /// Key that `/19/list` tokens are signed with.
+    cursor_secret: Arc<[u8]>,
+    /// Key that bearer tokens from `/19/token` are signed with.
+    token_secret: Arc<[u8]>,
+    /// How long bearer tokens from `/19/token` stay valid.
+    token_ttl: Duration,
+    /// Wakes up the webhook worker when there are new deliveries.
+    webhooks: Arc<Notify>,
+    /// Whether webhooks may be sent to addresses that aren't [`is_public`], which is off unless
+    /// `QUOTE_WEBHOOK_ALLOW_PRIVATE_URLS` is `true`, e.g. to try them out with a local receiver.
+    allow_private_webhooks: bool,
+}
+
+/// A quote as it was before it was changed or deleted.
+#[derive(FromRow, Serialize, Deserialize, Debug)]
+struct QuoteVersion {
+    quote_id: Uuid,
+    version: i32,
+    author: String,
+    quote: String,
+    recorded_at: DateTime<Utc>,
+}
+
+/// Reads an HMAC secret from the environment variable `name`. Without one, a random secret is made
+/// up, so whatever was signed with it stops being accepted after a restart.
+fn secret(name: &str) -> Arc<[u8]> {
+    match std::env::var(name) {
+        Ok(secret) if !secret.is_empty() => secret.into_bytes().into(),
+        _ => {
+            let mut secret = [0; 32];
+            rand::thread_rng().fill(&mut secret);
+            secret.into()
+        }
+    }
+}
+
+/// Records the current version of the quote with the given `id` before it's changed or deleted.
+async fn archive(tx: &mut Transaction<'_, Postgres>, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO quote_versions (quote_id, version, author, quote, owner)
+        SELECT id, version, author, quote, owner FROM quotes WHERE id = $1
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(id)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Turns an error from changing quotes into a response. Quotes from before the `CHECK` constraints
+/// on `quotes` may break them, and Postgres checks the whole row on every update, so such a quote
+/// can't be removed, restored or re-attributed until its text is fixed through `/19/undo`.
+/// `quote_violations` lists the quotes that need it.
+fn quote_update_error(e: sqlx::Error) -> Response {
+    match e {
+        sqlx::Error::RowNotFound => StatusCode::NOT_FOUND.into_response(),
+        sqlx::Error::Database(e) if e.is_check_violation() => {
+            let constraint = e.constraint().unwrap_or_default();
+            let error = format!(
+                "a quote breaks the `{constraint}` constraint and has to be fixed through /19/undo first"
+            );
+            (StatusCode::CONFLICT, Json(json!({ "error": error }))).into_response()
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Turns an error from saving a quote that passed [`validate_quote`] into a response. The `CHECK`
+/// constraints on `quotes` follow the same rules, but should they ever disagree, the field is
+/// reported as invalid like any other rather than failing with a 500.
+fn quote_save_error(e: sqlx::Error) -> Response {
+    match e {
+        sqlx::Error::Database(e) if e.is_check_violation() => {
+            let constraint = e.constraint().unwrap_or_default();
+            let field = if constraint.starts_with("quotes_author") {
+                "author"
+            } else {
+                "quote"
+            };
+
+            let mut errors = FieldErrors::default();
+            errors.add(field, format!("breaks the `{constraint}` constraint"));
+            errors.into_response()
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// What's wrong with each invalid field of a request, by field name.
+#[derive(Serialize, Default, Debug)]
+struct FieldErrors {
+    errors: BTreeMap<&'static str, Vec<String>>,
+}
+
+impl FieldErrors {
+    fn add(&mut self, field: &'static str, message: String) {
+        self.errors.entry(field).or_default().push(message);
+    }
+
+    /// Cleans up a text field by turning CRLF line endings into LF, trimming it and normalizing it
+    /// to NFC, then checks that what's left isn't empty, isn't longer than `max_length` characters
+    /// and has no control characters, apart from tabs and newlines if it's `multiline`.
+    fn check(
+        &mut self,
+        field: &'static str,
+        value: &str,
+        max_length: usize,
+        multiline: bool,
+    ) -> String {
+        let value: String = value.replace("\r\n", "\n").trim().nfc().collect();
+
+        if value.is_empty() {
+            self.add(field, "must not be empty".to_string());
+        }
+        let length = value.chars().count();
+        if length > max_length {
+            self.add(
+                field,
+                format!("must be at most {max_length} characters long, but is {length}"),
+            );
+        }
+        if value
+            .chars()
+            .any(|c| c.is_control() && !(multiline && matches!(c, '\t' | '\n')))
+        {
+            self.add(field, "must not contain control characters".to_string());
+        }
+
+        value
+    }
+}
+
+impl IntoResponse for FieldErrors {
+    fn into_response(self) -> Response {
+        (StatusCode::UNPROCESSABLE_ENTITY, Json(self)).into_response()
+    }
+}
+
+/// Cleans up the author and text of a quote, as long as both of them are valid.
+fn validate_quote(author: &str, quote: &str) -> Result<(String, String), FieldErrors> {
+    let mut errors = FieldErrors::default();
+    let author = errors.check("author", author, MAX_AUTHOR_LENGTH, false);
+    let quote = errors.check("quote", quote, MAX_QUOTE_LENGTH, true);
+
+    if errors.errors.is_empty() {
+        Ok((author, quote))
+    } else {
+        Err(errors)
+    }
+}
+
+/// Finds the author whose normalized name matches `name`, creating them if there's none yet.
+///
+/// Returns their ID and name. Quotes store that name rather than `name` as typed, so every
+/// quote by the same author shows the same spelling.
+async fn upsert_author(
+    tx: &mut Transaction<'_, Postgres>,
+    name: &str,
+) -> Result<(Uuid, String), sqlx::Error> {
+    sqlx::query_as(
+        "INSERT INTO authors (name, normalized_name)
+        VALUES (clean_author_name($1), normalize_author_name($1))
+        ON CONFLICT (normalized_name) DO UPDATE SET name = authors.name
+        RETURNING id, name",
+    )
+    .bind(name)
+    .fetch_one(&mut **tx)
+    .await
+}
+
+/// The `ETag` header for a quote, which is just its version.
+fn etag(quote: &Quote) -> [(header::HeaderName, String); 1] {
+    [(header::ETAG, format!("\"{}\"", quote.version))]
+}
+
+/// Reads the version from an `If-Match` header, if there is one.
+fn if_match(headers: &HeaderMap) -> Result<Option<i32>> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+
+    let version = value
+        .to_str()
+        .map_err(|_| StatusCode::BAD_REQUEST)?
+        .trim()
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .parse()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    Ok(Some(version))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, Debug, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+enum Role {
+    /// Can only touch their own quote book.
+    #[default]
+    Owner,
+    /// Can also reset every quote book, hand out keys and manage authors,
+    /// which are shared between quote books.
+    Admin,
+}
+
+/// Who a request is made by, going by either an API key in the `X-Api-Key` header or a bearer
+/// token from `/19/token`. Requests with neither share the anonymous quote book.
+#[derive(Default, Debug)]
+struct Identity {
+    owner: Option<String>,
+    role: Role,
+}
+
+/// Claims of the bearer tokens `/19/token` issues. Only tokens signed with this service's own
+/// secret, for this service and not yet expired are accepted, so JWTs signed elsewhere in the
+/// app, like those from `/16/wrap`, can't be passed off as one.
+#[derive(Serialize, Deserialize, Debug)]
+struct TokenClaims {
+    sub: String,
+    role: Role,
+    iss: String,
+    aud: String,
+    /// Expiry as a UNIX timestamp.
+    exp: u64,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Identity {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(key) = parts.headers.get(API_KEY_HEADER) {
+            let (owner, role): (String, Role) =
+                query_as("SELECT owner, role FROM api_keys WHERE key_hash = $1")
+                    .bind(hash_key(key.as_bytes()))
+                    .fetch_optional(&*state.pool)
+                    .await
+                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                    .ok_or(StatusCode::UNAUTHORIZED)?;
+
+            return Ok(Self {
+                owner: Some(owner),
+                role,
+            });
+        }
+
+        if let Some(authorization) = parts.headers.get(header::AUTHORIZATION) {
+            let token = authorization
+                .to_str()
+                .ok()
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .ok_or(StatusCode::UNAUTHORIZED)?;
+
+            let mut validation = jwt::Validation::new(jwt::Algorithm::HS256);
+            validation.leeway = 0;
+            validation.set_issuer(&[TOKEN_ISSUER]);
+            validation.set_audience(&[TOKEN_AUDIENCE]);
+            validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+            let token = jwt::decode::<TokenClaims>(
+                token,
+                &jwt::DecodingKey::from_secret(&state.token_secret),
+                &validation,
+            )
+            .map_err(|_| StatusCode::UNAUTHORIZED)?;
+
+            return Ok(Self {
+                owner: Some(token.claims.sub),
+                role: token.claims.role,
+            });
+        }
+
+        Ok(Self::default())
+    }
+}
+
+fn hash_key(key: &[u8]) -> Vec<u8> {
+    Sha256::digest(key).to_vec()
+}
+
+#[derive(Deserialize, Debug)]
+struct KeyRequest {
+    /// Defaults to the owner asking for the key.
+    owner: Option<String>,
+    role: Option<Role>,
+}
+
+#[derive(Serialize, Debug)]
+struct ApiKey {
+    key: String,
+    owner: String,
+    role: Role,
+}
+
+/// Hands out a new API key. Owners can get more keys for themselves, admins can get keys for anyone.
+async fn create_key(
+    State(state): State<AppState>,
+    identity: Identity,
+    Json(payload): Json<KeyRequest>,
+) -> Result<(StatusCode, Json<ApiKey>)> {
+    let pool = state.pool;
+
+    let Some(own) = identity.owner else {
+        return Err(StatusCode::UNAUTHORIZED.into());
+    };
+    let owner = payload.owner.unwrap_or_else(|| own.clone());
+    let role = payload.role.unwrap_or_default();
+    if identity.role != Role::Admin && (owner != own || role != Role::Owner) {
+        return Err(StatusCode::FORBIDDEN.into());
+    }
+
+    let key: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+
+    sqlx::query("INSERT INTO api_keys (key_hash, owner, role) VALUES ($1, $2, $3)")
+        .bind(hash_key(key.as_bytes()))
+        .bind(&owner)
+        .bind(role)
+        .execute(&*pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((StatusCode::CREATED, Json(ApiKey { key, owner, role })))
+}
+
+#[derive(Serialize, Debug)]
+struct BearerToken {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// Trades an API key for a short-lived bearer token with the same owner and role. Tokens can't be
+/// traded for new ones, so access never outlives the key it came from by more than a token's TTL.
+async fn create_token(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    identity: Identity,
+) -> Result<(StatusCode, Json<BearerToken>)> {
+    let Some(owner) = identity.owner else {
+        return Err(StatusCode::UNAUTHORIZED.into());
+    };
+    if !headers.contains_key(API_KEY_HEADER) {
+        return Err(StatusCode::FORBIDDEN.into());
+    }
+
+    let expires_at = Utc::now() + state.token_ttl;
+    let claims = TokenClaims {
+        sub: owner,
+        role: identity.role,
+        iss: TOKEN_ISSUER.to_string(),
+        aud: TOKEN_AUDIENCE.to_string(),
+        exp: expires_at.timestamp() as u64,
+    };
+
+    let token = jwt::encode(
+        &jwt::Header::new(jwt::Algorithm::HS256),
+        &claims,
+        &jwt::EncodingKey::from_secret(&state.token_secret),
+    )
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((StatusCode::CREATED, Json(BearerToken { token, expires_at })))
+}
+
+/// Makes `key` an admin key, owned by "admin".
+async fn register_admin_key(pool: Arc<PgPool>, key: String) {
+    let registered = sqlx::query(
+        "INSERT INTO api_keys (key_hash, owner, role) VALUES ($1, 'admin', 'admin')
+        ON CONFLICT (key_hash) DO UPDATE SET role = 'admin'",
+    )
+    .bind(hash_key(key.as_bytes()))
+    .execute(&*pool)
+    .await;
+
+    if let Err(e) = registered {
+        event!(Level::WARN, "Registering the admin key failed: {e}");
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ResetParams {
+    /// Reset every quote book instead of just the own one, which only admins may do.
+    #[serde(default)]
+    all: bool,
 }
 
-async fn reset(State(state): State<AppState>) -> Result<StatusCode> {
+async fn reset(
+    State(state): State<AppState>,
+    identity: Identity,
+    Query(params): Query<ResetParams>,
+) -> Result<StatusCode> {
     let pool = state.pool;
 
-    pool.execute("DELETE FROM quotes")
+    if params.all && identity.role != Role::Admin {
+        return Err(StatusCode::FORBIDDEN.into());
+    }
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    sqlx::query(
+        "INSERT INTO quote_versions (quote_id, version, author, quote, owner)
+        SELECT id, version, author, quote, owner FROM quotes
+        WHERE $1 OR owner IS NOT DISTINCT FROM $2
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(params.all)
+    .bind(identity.owner.as_deref())
+    .execute(&mut *tx)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    sqlx::query("DELETE FROM quotes WHERE $1 OR owner IS NOT DISTINCT FROM $2")
+        .bind(params.all)
+        .bind(identity.owner.as_deref())
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // Authors are shared, so they're only gone once no quote book has any of their quotes.
+    tx.execute(
+        "DELETE FROM authors WHERE NOT EXISTS (SELECT 1 FROM quotes WHERE author_id = authors.id)",
+    )
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let payload = json!({
+        "event": WebhookEvent::Reset,
+        "occurred_at": Utc::now(),
+        "all": params.all,
+    });
+    enqueue_webhooks(
+        &mut tx,
+        identity.owner.as_deref(),
+        params.all,
+        WebhookEvent::Reset,
+        &payload,
+    )
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    tx.commit()
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    state.webhooks.notify_one();
 
     Ok(StatusCode::OK)
 }
 
 async fn cite(
     State(state): State<AppState>,
+    identity: Identity,
     Path(id): Path<Uuid>,
-) -> Result<(StatusCode, Json<Quote>)> {
+) -> Result<(StatusCode, [(header::HeaderName, String); 1], Json<Quote>)> {
     let pool = state.pool;
 
-    let quote: Quote = query_as("SELECT * FROM quotes WHERE id = $1")
-        .bind(id)
-        .fetch_one(&*pool)
+    let quote: Quote = query_as(
+        "SELECT * FROM quotes WHERE id = $1 AND owner IS NOT DISTINCT FROM $2 AND deleted_at IS NULL",
+    )
+    .bind(id)
+    .bind(identity.owner.as_deref())
+    .fetch_one(&*pool)
         .await
         .map_err(|e| {
             if matches!(e, sqlx::Error::RowNotFound) {
@@ -78,168 +639,2319 @@ async fn cite(
             }
         })?;
 
-    Ok((StatusCode::OK, Json(quote)))
+    Ok((StatusCode::OK, etag(&quote), Json(quote)))
 }
 
-async fn remove(
+async fn history(
     State(state): State<AppState>,
+    identity: Identity,
     Path(id): Path<Uuid>,
-) -> Result<(StatusCode, Json<Quote>)> {
+) -> Result<(StatusCode, Json<Vec<QuoteVersion>>)> {
     let pool = state.pool;
 
-    let quote: Quote = query_as("DELETE FROM quotes WHERE id = $1 RETURNING *")
+    let versions: Vec<QuoteVersion> = query_as(
+        "SELECT * FROM quote_versions
+        WHERE quote_id = $1 AND owner IS NOT DISTINCT FROM $2
+        ORDER BY version ASC",
+    )
+    .bind(id)
+    .bind(identity.owner.as_deref())
+    .fetch_all(&*pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if versions.is_empty() {
+        // A quote that was never changed has no history, but it still exists.
+        let exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM quotes WHERE id = $1 AND owner IS NOT DISTINCT FROM $2)",
+        )
         .bind(id)
+        .bind(identity.owner.as_deref())
         .fetch_one(&*pool)
         .await
-        .map_err(|e| {
-            if matches!(e, sqlx::Error::RowNotFound) {
-                StatusCode::NOT_FOUND
-            } else {
-                StatusCode::INTERNAL_SERVER_ERROR
-            }
-        })?;
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        if !exists {
+            return Err(StatusCode::NOT_FOUND.into());
+        }
+    }
 
-    Ok((StatusCode::OK, Json(quote)))
+    Ok((StatusCode::OK, Json(versions)))
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct UndoRequest {
-    author: String,
-    quote: String,
+/// Tags are matched case-insensitively and without surrounding whitespace.
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
 }
 
-async fn undo(
+/// All tags of a quote that isn't in the trash, in alphabetical order.
+async fn fetch_tags<'e>(
+    executor: impl Executor<'e, Database = Postgres>,
+    id: Uuid,
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT tag FROM quote_tags
+        WHERE quote_id = (SELECT id FROM quotes WHERE id = $1 AND deleted_at IS NULL)
+        ORDER BY tag ASC",
+    )
+    .bind(id)
+    .fetch_all(executor)
+    .await
+}
+
+/// Checks that the quote with the given `id` is in the quote book of `owner` and not in the trash.
+async fn ensure_quote<'e>(
+    executor: impl Executor<'e, Database = Postgres>,
+    id: Uuid,
+    owner: Option<&str>,
+) -> Result<()> {
+    let exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS (
+            SELECT 1 FROM quotes WHERE id = $1 AND owner IS NOT DISTINCT FROM $2 AND deleted_at IS NULL
+        )",
+    )
+    .bind(id)
+    .bind(owner)
+    .fetch_one(executor)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if exists {
+        Ok(())
+    } else {
+        Err(StatusCode::NOT_FOUND.into())
+    }
+}
+
+async fn tags(
     State(state): State<AppState>,
+    identity: Identity,
     Path(id): Path<Uuid>,
-    Json(payload): Json<UndoRequest>,
-) -> Result<(StatusCode, Json<Quote>)> {
+) -> Result<(StatusCode, Json<Vec<String>>)> {
     let pool = state.pool;
 
-    let quote: Quote = query_as("UPDATE quotes SET author = $1, quote = $2, version = version + 1 WHERE id = $3 RETURNING *")
-        .bind(payload.author)
-        .bind(payload.quote)
-        .bind(id)
-        .fetch_one(&*pool)
+    ensure_quote(&*pool, id, identity.owner.as_deref()).await?;
+    let tags = fetch_tags(&*pool, id)
         .await
-        .map_err(|e| {
-            if matches!(e, sqlx::Error::RowNotFound) {
-                StatusCode::NOT_FOUND
-            } else {
-                StatusCode::INTERNAL_SERVER_ERROR
-            }
-        })?;
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    Ok((StatusCode::OK, Json(quote)))
+    Ok((StatusCode::OK, Json(tags)))
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct DraftRequest {
-    author: String,
-    quote: String,
+#[derive(Deserialize, Debug)]
+struct TagRequest {
+    tags: Vec<String>,
 }
 
-async fn draft(
+/// Adds tags to a quote, keeping the ones it already has.
+async fn add_tags(
     State(state): State<AppState>,
-    Json(payload): Json<DraftRequest>,
-) -> Result<(StatusCode, Json<Quote>)> {
+    identity: Identity,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<TagRequest>,
+) -> Result<(StatusCode, Json<Vec<String>>)> {
     let pool = state.pool;
 
-    let quote: Quote =
-        query_as("INSERT INTO quotes (id, author, quote) VALUES($1, $2, $3) RETURNING *")
-            .bind(Uuid::new_v4())
-            .bind(payload.author)
-            .bind(payload.quote)
-            .fetch_one(&*pool)
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let new_tags: Vec<String> = payload.tags.iter().map(|tag| normalize_tag(tag)).collect();
+    if new_tags.iter().any(String::is_empty) {
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
 
-    Ok((StatusCode::CREATED, Json(quote)))
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    ensure_quote(&mut *tx, id, identity.owner.as_deref()).await?;
+    sqlx::query(
+        "INSERT INTO quote_tags (quote_id, tag) SELECT $1, UNNEST($2::TEXT[]) ON CONFLICT DO NOTHING",
+    )
+    .bind(id)
+    .bind(&new_tags)
+    .execute(&mut *tx)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let tags = fetch_tags(&mut *tx, id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    tx.commit()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((StatusCode::OK, Json(tags)))
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Page {
-    quotes: Vec<Quote>,
-    page: usize,
-    next_token: Option<String>,
+async fn remove_tag(
+    State(state): State<AppState>,
+    identity: Identity,
+    Path((id, tag)): Path<(Uuid, String)>,
+) -> Result<(StatusCode, Json<Vec<String>>)> {
+    let pool = state.pool;
+
+    ensure_quote(&*pool, id, identity.owner.as_deref()).await?;
+    let removed = sqlx::query("DELETE FROM quote_tags WHERE quote_id = $1 AND tag = $2")
+        .bind(id)
+        .bind(normalize_tag(&tag))
+        .execute(&*pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if removed.rows_affected() == 0 {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+
+    let tags = fetch_tags(&*pool, id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((StatusCode::OK, Json(tags)))
 }
 
-async fn list(
+#[derive(Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, Debug, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+enum ReactionKind {
+    #[default]
+    Like,
+    Favourite,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReactionParams {
+    #[serde(default)]
+    kind: ReactionKind,
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug)]
+struct Reactions {
+    likes: i64,
+    favourites: i64,
+}
+
+/// Who's reacting, which has to be an authenticated owner. Anonymous requests can't be told
+/// apart reliably, anything they send to identify themselves can just be changed for every
+/// reaction, so they can't react at all.
+fn reactor(identity: &Identity) -> Result<&str> {
+    let owner = identity.owner.as_deref().ok_or(StatusCode::UNAUTHORIZED)?;
+
+    Ok(owner)
+}
+
+async fn fetch_reactions<'e>(
+    executor: impl Executor<'e, Database = Postgres>,
+    id: Uuid,
+) -> Result<Reactions, sqlx::Error> {
+    query_as(
+        "SELECT
+            COUNT(*) FILTER (WHERE kind = 'like') AS likes,
+            COUNT(*) FILTER (WHERE kind = 'favourite') AS favourites
+        FROM quote_reactions
+        WHERE quote_id = $1",
+    )
+    .bind(id)
+    .fetch_one(executor)
+    .await
+}
+
+/// Reacts to a quote. Reacting the same way twice only counts once per owner.
+async fn like(
     State(state): State<AppState>,
-    Query(params): Query<HashMap<String, String>>,
-) -> Result<(StatusCode, Json<Page>)> {
+    identity: Identity,
+    Path(id): Path<Uuid>,
+    Query(params): Query<ReactionParams>,
+) -> Result<(StatusCode, Json<Reactions>)> {
+    let pool = state.pool;
+
+    let reactor = reactor(&identity)?;
+    ensure_quote(&*pool, id, identity.owner.as_deref()).await?;
+
+    let added = sqlx::query(
+        "INSERT INTO quote_reactions (quote_id, owner, kind) VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(id)
+    .bind(reactor)
+    .bind(params.kind)
+    .execute(&*pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let reactions = fetch_reactions(&*pool, id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if added.rows_affected() == 0 {
+        Ok((StatusCode::OK, Json(reactions)))
+    } else {
+        Ok((StatusCode::CREATED, Json(reactions)))
+    }
+}
+
+async fn unlike(
+    State(state): State<AppState>,
+    identity: Identity,
+    Path(id): Path<Uuid>,
+    Query(params): Query<ReactionParams>,
+) -> Result<(StatusCode, Json<Reactions>)> {
     let pool = state.pool;
-    let mut pagination = state.pagination.lock().await;
 
-    if let Some(t) = params.get("token") {
-        // Check if the token exists
-        let (id, num) = pagination.get_mut(t).ok_or(StatusCode::BAD_REQUEST)?;
-        let num = *num + 1;
-        // Query from cursor
-        let mut quotes: Vec<Quote> = query_as("SELECT * FROM quotes WHERE created_at > (SELECT created_at FROM quotes WHERE id = $1) ORDER BY created_at ASC LIMIT 4")
-            .bind(*id)
-            .fetch_all(&*pool)
+    let reactor = reactor(&identity)?;
+    ensure_quote(&*pool, id, identity.owner.as_deref()).await?;
+
+    let removed =
+        sqlx::query("DELETE FROM quote_reactions WHERE quote_id = $1 AND owner = $2 AND kind = $3")
+            .bind(id)
+            .bind(reactor)
+            .bind(params.kind)
+            .execute(&*pool)
             .await
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if removed.rows_affected() == 0 {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
 
-        // Store token if there are more pages
-        let token = if quotes.len() == 4 {
-            let token: String = rand::thread_rng()
-                .sample_iter(&Alphanumeric)
-                .take(16)
-                .map(char::from)
-                .collect();
+    let reactions = fetch_reactions(&*pool, id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-            pagination.insert(token.clone(), (quotes[2].id, num));
+    Ok((StatusCode::OK, Json(reactions)))
+}
 
-            // We only return 3 quotes per page.
-            // The 4th one was only fetched to check if there are more pages.
-            quotes.pop();
+async fn remove(
+    State(state): State<AppState>,
+    identity: Identity,
+    Path(id): Path<Uuid>,
+) -> Result<(StatusCode, Json<Quote>)> {
+    let pool = state.pool;
 
-            Some(token)
-        } else {
-            None
-        };
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    archive(&mut tx, id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-        let page = Page {
-            quotes,
-            page: num,
-            next_token: token,
-        };
+    let quote: Quote = query_as(
+        "UPDATE quotes SET deleted_at = CURRENT_TIMESTAMP
+        WHERE id = $1 AND owner IS NOT DISTINCT FROM $2 AND deleted_at IS NULL
+        RETURNING *",
+    )
+    .bind(id)
+    .bind(identity.owner.as_deref())
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(quote_update_error)?;
 
-        Ok((StatusCode::OK, Json(page)))
-    } else {
-        // Query normally
-        let mut quotes: Vec<Quote> =
-            query_as("SELECT * FROM quotes ORDER BY created_at ASC LIMIT 4")
-                .fetch_all(&*pool)
-                .await
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    enqueue_quote_webhooks(
+        &mut tx,
+        WebhookEvent::Remove,
+        &quote,
+        identity.owner.as_deref(),
+    )
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-        // Store token if there are more pages
-        let token = if quotes.len() == 4 {
-            let token: String = rand::thread_rng()
-                .sample_iter(&Alphanumeric)
-                .take(16)
-                .map(char::from)
-                .collect();
+    tx.commit()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    state.webhooks.notify_one();
 
-            pagination.insert(token.clone(), (quotes[2].id, 1));
+    Ok((StatusCode::OK, Json(quote)))
+}
 
-            // We only return 3 quotes per page.
-            // The 4th one was only fetched to check if there are more pages.
-            quotes.pop();
+#[derive(Serialize, Deserialize, Debug)]
+struct UndoRequest {
+    author: String,
+    quote: String,
+}
 
-            Some(token)
-        } else {
-            None
-        };
+#[derive(Deserialize, Debug)]
+struct UndoParams {
+    /// Restore the author and quote of this stored version instead of taking them from the body.
+    to_version: Option<i32>,
+}
 
-        let page = Page {
-            quotes,
+async fn undo(
+    State(state): State<AppState>,
+    identity: Identity,
+    Path(id): Path<Uuid>,
+    Query(params): Query<UndoParams>,
+    headers: HeaderMap,
+    payload: Option<Json<UndoRequest>>,
+) -> Result<(StatusCode, [(header::HeaderName, String); 1], Json<Quote>)> {
+    let pool = state.pool;
+    let expected = if_match(&headers)?;
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let current: Quote = query_as(
+        "SELECT * FROM quotes
+        WHERE id = $1 AND owner IS NOT DISTINCT FROM $2 AND deleted_at IS NULL
+        FOR UPDATE",
+    )
+    .bind(id)
+    .bind(identity.owner.as_deref())
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| {
+        if matches!(e, sqlx::Error::RowNotFound) {
+            StatusCode::NOT_FOUND
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })?;
+    if expected.is_some_and(|version| version != current.version) {
+        return Err(StatusCode::PRECONDITION_FAILED.into());
+    }
+
+    let (author, quote) = match (payload, params.to_version) {
+        (Some(Json(payload)), None) => (payload.author, payload.quote),
+        (None, Some(version)) if version == current.version => (current.author, current.quote),
+        (None, Some(version)) => {
+            let stored: QuoteVersion =
+                query_as("SELECT * FROM quote_versions WHERE quote_id = $1 AND version = $2")
+                    .bind(id)
+                    .bind(version)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(|e| {
+                        if matches!(e, sqlx::Error::RowNotFound) {
+                            StatusCode::NOT_FOUND
+                        } else {
+                            StatusCode::INTERNAL_SERVER_ERROR
+                        }
+                    })?;
+
+            (stored.author, stored.quote)
+        }
+        _ => return Err(StatusCode::BAD_REQUEST.into()),
+    };
+    // Versions from before quotes were validated may not be valid anymore.
+    let (author, quote) = validate_quote(&author, &quote)?;
+
+    archive(&mut tx, id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (author_id, author) = upsert_author(&mut tx, &author)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let quote: Quote = query_as("UPDATE quotes SET author = $1, author_id = $2, quote = $3, version = version + 1 WHERE id = $4 RETURNING *")
+        .bind(author)
+        .bind(author_id)
+        .bind(quote)
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(quote_save_error)?;
+
+    enqueue_quote_webhooks(
+        &mut tx,
+        WebhookEvent::Undo,
+        &quote,
+        identity.owner.as_deref(),
+    )
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    tx.commit()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    state.webhooks.notify_one();
+
+    Ok((StatusCode::OK, etag(&quote), Json(quote)))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct DraftRequest {
+    author: String,
+    quote: String,
+}
+
+async fn draft(
+    State(state): State<AppState>,
+    identity: Identity,
+    Json(payload): Json<DraftRequest>,
+) -> Result<(StatusCode, Json<Quote>)> {
+    let pool = state.pool;
+
+    let (author, quote) = validate_quote(&payload.author, &payload.quote)?;
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (author_id, author) = upsert_author(&mut tx, &author)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let quote: Quote = query_as(
+        "INSERT INTO quotes (id, author, author_id, quote, owner) VALUES($1, $2, $3, $4, $5) RETURNING *",
+    )
+    .bind(Uuid::new_v4())
+    .bind(author)
+    .bind(author_id)
+    .bind(quote)
+    .bind(identity.owner.as_deref())
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(quote_save_error)?;
+
+    enqueue_quote_webhooks(
+        &mut tx,
+        WebhookEvent::Draft,
+        &quote,
+        identity.owner.as_deref(),
+    )
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    tx.commit()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    state.webhooks.notify_one();
+
+    Ok((StatusCode::CREATED, Json(quote)))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Page<T = Quote> {
+    quotes: Vec<T>,
+    page: usize,
+    next_token: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ListParams {
+    token: Option<String>,
+    /// Only honoured on the first page, later pages keep the size their token was issued with.
+    page_size: Option<i64>,
+    tag: Option<String>,
+    /// Only list quotes by this author, matched on their normalized name.
+    author: Option<String>,
+    /// Only list quotes created at or after this time.
+    since: Option<DateTime<Utc>>,
+    /// Only list quotes created before this time.
+    until: Option<DateTime<Utc>>,
+}
+
+/// Which quotes are listed. Tokens carry the filter they were issued for,
+/// so that every page of a listing is filtered the same way.
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+struct ListFilter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    author_id: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    since: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    until: Option<DateTime<Utc>>,
+}
+
+/// Where the next page of `/19/list` or `/19/authors/:id/quotes` starts, signed so it can't be
+/// tampered with and needs no server-side state to resolve.
+#[derive(Serialize, Deserialize, Debug)]
+struct Cursor {
+    /// `created_at` of the last quote on the previous page.
+    created_at: DateTime<Utc>,
+    /// `id` of the last quote on the previous page, which breaks ties on `created_at`.
+    id: Uuid,
+    #[serde(flatten)]
+    filter: ListFilter,
+    /// Number of the previous page.
+    page: usize,
+    page_size: i64,
+    /// Expiry as a UNIX timestamp.
+    exp: u64,
+}
+
+/// A pagination cursor. Every kind of cursor is signed with the same secret, so tokens carry
+/// their kind, and one kind of cursor can't be passed off as another with overlapping fields.
+trait CursorKind: Serialize + DeserializeOwned {
+    /// The `kind` claim of tokens for this cursor.
+    const KIND: &'static str;
+}
+
+impl CursorKind for Cursor {
+    const KIND: &'static str = "list";
+}
+
+impl CursorKind for TopCursor {
+    const KIND: &'static str = "top";
+}
+
+/// The claims of a cursor token.
+#[derive(Serialize, Deserialize)]
+struct CursorClaims<C> {
+    kind: String,
+    #[serde(flatten)]
+    cursor: C,
+}
+
+/// Signs a pagination cursor, which needs an `exp` claim, into a token. Cursors have a secret of
+/// their own, so no other token the app signs can be passed off as one.
+fn encode_cursor<C: CursorKind>(state: &AppState, cursor: C) -> Result<String> {
+    let claims = CursorClaims {
+        kind: C::KIND.to_owned(),
+        cursor,
+    };
+    let token = jwt::encode(
+        &jwt::Header::new(jwt::Algorithm::HS256),
+        &claims,
+        &jwt::EncodingKey::from_secret(&state.cursor_secret),
+    )
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(token)
+}
+
+/// Reads a pagination cursor back from a token, as long as it's genuine, of the right kind and
+/// hasn't expired.
+fn decode_cursor<C: CursorKind>(state: &AppState, token: &str) -> Result<C> {
+    let mut validation = jwt::Validation::new(jwt::Algorithm::HS256);
+    validation.leeway = 0;
+
+    let token = jwt::decode::<CursorClaims<C>>(
+        token,
+        &jwt::DecodingKey::from_secret(&state.cursor_secret),
+        &validation,
+    )
+    .map_err(|_| StatusCode::BAD_REQUEST)?;
+    if token.claims.kind != C::KIND {
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
+
+    Ok(token.claims.cursor)
+}
+
+/// The number and size of the page after the one a cursor was issued for. Cursors are only ever
+/// issued with sane values, but they're checked again rather than trusted with the query.
+fn next_page(page: usize, page_size: i64) -> Result<(usize, i64)> {
+    let page = page.checked_add(1).ok_or(StatusCode::BAD_REQUEST)?;
+    if !(1..=MAX_PAGE_SIZE).contains(&page_size) {
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
+
+    Ok((page, page_size))
+}
+
+async fn list(
+    State(state): State<AppState>,
+    identity: Identity,
+    Query(params): Query<ListParams>,
+) -> Result<(StatusCode, Json<Page>)> {
+    let page = paginate(&state, &identity, None, params).await?;
+
+    Ok((StatusCode::OK, Json(page)))
+}
+
+/// A page of quotes from the quote book of `identity`, optionally only those by the author
+/// with the given id.
+async fn paginate(
+    state: &AppState,
+    identity: &Identity,
+    author_id: Option<Uuid>,
+    params: ListParams,
+) -> Result<Page> {
+    let filter = ListFilter {
+        author_id,
+        author: params.author,
+        tag: params.tag.as_deref().map(normalize_tag),
+        since: params.since,
+        until: params.until,
+    };
+
+    let (filter, after, page, page_size) = match params.token {
+        Some(token) => {
+            let cursor: Cursor = decode_cursor(state, &token)?;
+            // A token only continues the listing it was issued for. Repeating its filter is fine,
+            // but leaving it out is too.
+            let unfiltered = ListFilter {
+                author_id,
+                ..Default::default()
+            };
+            if filter != cursor.filter && filter != unfiltered {
+                return Err(StatusCode::BAD_REQUEST.into());
+            }
+            let (page, page_size) = next_page(cursor.page, cursor.page_size)?;
+            (
+                cursor.filter,
+                Some((cursor.created_at, cursor.id)),
+                page,
+                page_size,
+            )
+        }
+        None => {
+            let page_size = params.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+            if !(1..=MAX_PAGE_SIZE).contains(&page_size) {
+                return Err(StatusCode::BAD_REQUEST.into());
+            }
+            (filter, None, 1, page_size)
+        }
+    };
+
+    // One more quote than fits on the page is fetched to check if there are more pages.
+    let mut quotes: Vec<Quote> = query_as(
+        "SELECT * FROM quotes
+        WHERE deleted_at IS NULL
+        AND owner IS NOT DISTINCT FROM $9
+        AND ($1::UUID IS NULL OR author_id = $1)
+        AND ($2::TEXT IS NULL OR author_id IN (
+            SELECT id FROM authors WHERE normalized_name = normalize_author_name($2)
+        ))
+        AND ($3::TEXT IS NULL OR EXISTS (
+            SELECT 1 FROM quote_tags WHERE quote_id = quotes.id AND tag = $3
+        ))
+        AND ($4::TIMESTAMPTZ IS NULL OR created_at >= $4)
+        AND ($5::TIMESTAMPTZ IS NULL OR created_at < $5)
+        AND ($6::TIMESTAMPTZ IS NULL OR (created_at, id) > ($6, $7::UUID))
+        ORDER BY created_at ASC, id ASC
+        LIMIT $8",
+    )
+    .bind(filter.author_id)
+    .bind(&filter.author)
+    .bind(&filter.tag)
+    .bind(filter.since)
+    .bind(filter.until)
+    .bind(after.map(|(created_at, _)| created_at))
+    .bind(after.map(|(_, id)| id))
+    .bind(page_size + 1)
+    .bind(identity.owner.as_deref())
+    .fetch_all(&*state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let next_token = if quotes.len() as i64 > page_size {
+        quotes.pop();
+        let last = quotes.last().ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        let exp = Utc::now().timestamp() as u64 + state.cursor_ttl.as_secs();
+        let cursor = Cursor {
+            created_at: last.created_at,
+            id: last.id,
+            filter,
+            page,
+            page_size,
+            exp,
+        };
+
+        Some(encode_cursor(state, cursor)?)
+    } else {
+        None
+    };
+
+    Ok(Page {
+        quotes,
+        page,
+        next_token,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+struct TopParams {
+    /// How far back reactions count, like `24h`, `7d` or `4w`. Defaults to `7d`.
+    window: Option<String>,
+    token: Option<String>,
+    /// Only honoured on the first page, later pages keep the size their token was issued with.
+    page_size: Option<i64>,
+}
+
+/// Parses a window like `7d` into its length.
+fn parse_window(window: &str) -> Option<Duration> {
+    let unit = match window.chars().last()? {
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    let count: u64 = window[..window.len() - 1].parse().ok()?;
+
+    Some(Duration::from_secs(count.checked_mul(unit)?))
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug)]
+struct RankedQuote {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    quote: Quote,
+    /// Reactions to the quote within the window.
+    reactions: i64,
+}
+
+/// Where the next page of `/19/top` starts. The window is fixed when the first page is fetched,
+/// so that reactions coming in while paging don't reshuffle the ranking.
+#[derive(Serialize, Deserialize, Debug)]
+struct TopCursor {
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    /// Reactions to the last quote on the previous page.
+    reactions: i64,
+    /// `created_at` of the last quote on the previous page, which breaks ties on `reactions`.
+    created_at: DateTime<Utc>,
+    /// `id` of the last quote on the previous page, which breaks ties on `created_at`.
+    id: Uuid,
+    /// Number of the previous page.
+    page: usize,
+    page_size: i64,
+    /// Expiry as a UNIX timestamp.
+    exp: u64,
+}
+
+/// Ranks quotes with at least one reaction by how many they got within a recent time window.
+async fn top(
+    State(state): State<AppState>,
+    identity: Identity,
+    Query(params): Query<TopParams>,
+) -> Result<(StatusCode, Json<Page<RankedQuote>>)> {
+    let pool = state.pool.clone();
+
+    let (since, until, after, page, page_size) = match params.token {
+        Some(token) => {
+            let cursor: TopCursor = decode_cursor(&state, &token)?;
+            let (page, page_size) = next_page(cursor.page, cursor.page_size)?;
+            (
+                cursor.since,
+                cursor.until,
+                Some((cursor.reactions, cursor.created_at, cursor.id)),
+                page,
+                page_size,
+            )
+        }
+        None => {
+            let window = parse_window(params.window.as_deref().unwrap_or("7d"))
+                .filter(|window| *window <= MAX_TOP_WINDOW)
+                .ok_or(StatusCode::BAD_REQUEST)?;
+            let page_size = params.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+            if !(1..=MAX_PAGE_SIZE).contains(&page_size) {
+                return Err(StatusCode::BAD_REQUEST.into());
+            }
+
+            let until = Utc::now();
+            let since = until - window;
+            (since, until, None, 1, page_size)
+        }
+    };
+
+    // One more quote than fits on the page is fetched to check if there are more pages.
+    let mut quotes: Vec<RankedQuote> = query_as(
+        "WITH ranked AS (
+            SELECT quotes.*, COUNT(*) AS reactions
+            FROM quotes
+            JOIN quote_reactions ON quote_reactions.quote_id = quotes.id
+                AND quote_reactions.created_at >= $2
+                AND quote_reactions.created_at < $3
+            WHERE quotes.owner IS NOT DISTINCT FROM $1 AND quotes.deleted_at IS NULL
+            GROUP BY quotes.id
+        )
+        SELECT * FROM ranked
+        WHERE $4::BIGINT IS NULL
+            OR reactions < $4
+            OR (reactions = $4 AND (created_at, id) > ($5, $6::UUID))
+        ORDER BY reactions DESC, created_at ASC, id ASC
+        LIMIT $7",
+    )
+    .bind(identity.owner)
+    .bind(since)
+    .bind(until)
+    .bind(after.map(|(reactions, _, _)| reactions))
+    .bind(after.map(|(_, created_at, _)| created_at))
+    .bind(after.map(|(_, _, id)| id))
+    .bind(page_size + 1)
+    .fetch_all(&*pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let next_token = if quotes.len() as i64 > page_size {
+        quotes.pop();
+        let last = quotes.last().ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        let exp = Utc::now().timestamp() as u64 + state.cursor_ttl.as_secs();
+        let cursor = TopCursor {
+            since,
+            until,
+            reactions: last.reactions,
+            created_at: last.quote.created_at,
+            id: last.quote.id,
+            page,
+            page_size,
+            exp,
+        };
+
+        Some(encode_cursor(&state, cursor)?)
+    } else {
+        None
+    };
+
+    let page = Page {
+        quotes,
+        page,
+        next_token,
+    };
+
+    Ok((StatusCode::OK, Json(page)))
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchParams {
+    /// Full-text query, in the syntax of Postgres' `websearch_to_tsquery`.
+    q: Option<String>,
+    /// Only return quotes by this author, matched on their normalized name.
+    author: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug)]
+struct SearchResult {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    quote: Quote,
+    rank: f32,
+    /// The quote as HTML, escaped, with every match wrapped in `<mark>` tags.
+    highlight: String,
+}
+
+async fn search(
+    State(state): State<AppState>,
+    identity: Identity,
+    Query(params): Query<SearchParams>,
+) -> Result<(StatusCode, Json<Vec<SearchResult>>)> {
+    let pool = state.pool;
+
+    if params.q.is_none() && params.author.is_none() {
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
+    let limit = params.limit.unwrap_or(20);
+    if !(1..=100).contains(&limit) {
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
+
+    let results: Vec<SearchResult> = query_as(
+        "SELECT *,
+            ts_rank(search, query) AS rank,
+            ts_headline(
+                'english',
+                escape_html(quote),
+                query,
+                'StartSel=<mark>, StopSel=</mark>, HighlightAll=true'
+            ) AS highlight
+        FROM quotes, websearch_to_tsquery('english', $1) query
+        WHERE deleted_at IS NULL
+            AND owner IS NOT DISTINCT FROM $4
+            AND ($1 = '' OR search @@ query)
+            AND ($2::TEXT IS NULL OR author_id IN (
+                SELECT id FROM authors WHERE normalized_name = normalize_author_name($2)
+            ))
+        ORDER BY rank DESC, created_at ASC
+        LIMIT $3",
+    )
+    .bind(params.q.unwrap_or_default())
+    .bind(params.author)
+    .bind(limit)
+    .bind(identity.owner)
+    .fetch_all(&*pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((StatusCode::OK, Json(results)))
+}
+
+#[derive(Deserialize, Debug)]
+struct RandomParams {
+    /// Only pick from quotes by this author, matched on their normalized name.
+    author: Option<String>,
+    tag: Option<String>,
+}
+
+/// Picks a random quote, every matching one with the same odds. The matching quotes are counted
+/// and a random number of them skipped, so this costs a scan of the quote book's matching quotes.
+async fn random(
+    State(state): State<AppState>,
+    identity: Identity,
+    Query(params): Query<RandomParams>,
+) -> Result<(StatusCode, Json<Quote>)> {
+    let pool = state.pool;
+
+    let quote: Quote = query_as(
+        "WITH matching AS MATERIALIZED (
+            SELECT id FROM quotes
+            WHERE owner IS NOT DISTINCT FROM $1
+            AND deleted_at IS NULL
+            AND ($2::TEXT IS NULL OR author_id IN (
+                SELECT id FROM authors WHERE normalized_name = normalize_author_name($2)
+            ))
+            AND ($3::TEXT IS NULL OR EXISTS (
+                SELECT 1 FROM quote_tags WHERE quote_id = quotes.id AND tag = $3
+            ))
+        )
+        SELECT quotes.* FROM quotes
+        WHERE id = (
+            SELECT id FROM matching
+            OFFSET floor(random() * (SELECT COUNT(*) FROM matching))
+            LIMIT 1
+        )",
+    )
+    .bind(identity.owner)
+    .bind(params.author)
+    .bind(params.tag.as_deref().map(normalize_tag))
+    .fetch_one(&*pool)
+    .await
+    .map_err(|e| {
+        if matches!(e, sqlx::Error::RowNotFound) {
+            StatusCode::NOT_FOUND
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })?;
+
+    Ok((StatusCode::OK, Json(quote)))
+}
+
+#[derive(Deserialize, Debug)]
+struct DailyParams {
+    /// Defaults to today, in UTC.
+    date: Option<NaiveDate>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct DailyQuote {
+    date: NaiveDate,
+    #[serde(flatten)]
+    quote: Quote,
+}
+
+/// The quote of the day. Every quote is ranked by a hash of its id and the date,
+/// so the pick only depends on those two and is the same on every instance.
+async fn daily(
+    State(state): State<AppState>,
+    identity: Identity,
+    Query(params): Query<DailyParams>,
+) -> Result<(StatusCode, Json<DailyQuote>)> {
+    let pool = state.pool;
+
+    let date = params.date.unwrap_or_else(|| Utc::now().date_naive());
+
+    let quote: Quote = query_as(
+        "SELECT * FROM quotes
+        WHERE owner IS NOT DISTINCT FROM $1 AND deleted_at IS NULL
+        ORDER BY md5(id::TEXT || $2::TEXT) ASC, id ASC
+        LIMIT 1",
+    )
+    .bind(identity.owner)
+    .bind(date.to_string())
+    .fetch_one(&*pool)
+    .await
+    .map_err(|e| {
+        if matches!(e, sqlx::Error::RowNotFound) {
+            StatusCode::NOT_FOUND
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })?;
+
+    Ok((StatusCode::OK, Json(DailyQuote { date, quote })))
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug)]
+struct TrashedQuote {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    quote: Quote,
+    deleted_at: DateTime<Utc>,
+}
+
+async fn trash(
+    State(state): State<AppState>,
+    identity: Identity,
+) -> Result<(StatusCode, Json<Vec<TrashedQuote>>)> {
+    let pool = state.pool;
+
+    let quotes: Vec<TrashedQuote> = query_as(
+        "SELECT * FROM quotes
+        WHERE owner IS NOT DISTINCT FROM $1 AND deleted_at IS NOT NULL
+        ORDER BY deleted_at DESC",
+    )
+    .bind(identity.owner)
+    .fetch_all(&*pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((StatusCode::OK, Json(quotes)))
+}
+
+async fn restore(
+    State(state): State<AppState>,
+    identity: Identity,
+    Path(id): Path<Uuid>,
+) -> Result<(StatusCode, Json<Quote>)> {
+    let pool = state.pool;
+
+    let quote: Quote = query_as(
+        "UPDATE quotes SET deleted_at = NULL
+        WHERE id = $1 AND owner IS NOT DISTINCT FROM $2 AND deleted_at IS NOT NULL
+        RETURNING *",
+    )
+    .bind(id)
+    .bind(identity.owner)
+    .fetch_one(&*pool)
+    .await
+    .map_err(quote_update_error)?;
+
+    Ok((StatusCode::OK, Json(quote)))
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug)]
+struct Author {
+    id: Uuid,
+    name: String,
+    /// Number of quotes by the author in the quote book of whoever asked,
+    /// not counting those in the trash.
+    quotes: i64,
+}
+
+/// Looks up an author along with the number of their quotes in the quote book of `owner`.
+async fn fetch_author<'e>(
+    executor: impl Executor<'e, Database = Postgres>,
+    id: Uuid,
+    owner: Option<&str>,
+) -> Result<Author> {
+    let author: Author = query_as(
+        "SELECT authors.id, authors.name, COUNT(quotes.id) AS quotes
+        FROM authors
+        LEFT JOIN quotes ON quotes.author_id = authors.id
+            AND quotes.owner IS NOT DISTINCT FROM $2
+            AND quotes.deleted_at IS NULL
+        WHERE authors.id = $1
+        GROUP BY authors.id",
+    )
+    .bind(id)
+    .bind(owner)
+    .fetch_one(executor)
+    .await
+    .map_err(|e| {
+        if matches!(e, sqlx::Error::RowNotFound) {
+            StatusCode::NOT_FOUND
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })?;
+
+    Ok(author)
+}
+
+/// All authors with quotes in the quote book of whoever asked, including those in the trash.
+async fn authors(
+    State(state): State<AppState>,
+    identity: Identity,
+) -> Result<(StatusCode, Json<Vec<Author>>)> {
+    let pool = state.pool;
+
+    let authors: Vec<Author> = query_as(
+        "SELECT authors.id, authors.name, COUNT(quotes.id) FILTER (WHERE quotes.deleted_at IS NULL) AS quotes
+        FROM authors
+        JOIN quotes ON quotes.author_id = authors.id AND quotes.owner IS NOT DISTINCT FROM $1
+        GROUP BY authors.id
+        ORDER BY authors.normalized_name ASC",
+    )
+    .bind(identity.owner)
+    .fetch_all(&*pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((StatusCode::OK, Json(authors)))
+}
+
+async fn author_quotes(
+    State(state): State<AppState>,
+    identity: Identity,
+    Path(id): Path<Uuid>,
+    Query(params): Query<ListParams>,
+) -> Result<(StatusCode, Json<Page>)> {
+    fetch_author(&*state.pool, id, identity.owner.as_deref()).await?;
+
+    let page = paginate(&state, &identity, Some(id), params).await?;
+
+    Ok((StatusCode::OK, Json(page)))
+}
+
+#[derive(Deserialize, Debug)]
+struct RenameRequest {
+    name: String,
+}
+
+/// Renames an author, along with the author of all of their quotes in every quote book.
+async fn rename_author(
+    State(state): State<AppState>,
+    identity: Identity,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<RenameRequest>,
+) -> Result<(StatusCode, Json<Author>)> {
+    let pool = state.pool;
+
+    if identity.role != Role::Admin {
+        return Err(StatusCode::FORBIDDEN.into());
+    }
+
+    let mut errors = FieldErrors::default();
+    let name = errors.check("name", &payload.name, MAX_AUTHOR_LENGTH, false);
+    if !errors.errors.is_empty() {
+        return Err(errors.into());
+    }
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    sqlx::query(
+        "UPDATE authors SET name = clean_author_name($1), normalized_name = normalize_author_name($1)
+        WHERE id = $2
+        RETURNING id",
+    )
+    .bind(name)
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+        // Someone else already goes by that name, which calls for a merge instead.
+        sqlx::Error::Database(e) if e.is_unique_violation() => StatusCode::CONFLICT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    })?;
+
+    reattribute(&mut tx, id, id)
+        .await
+        .map_err(quote_update_error)?;
+    let author = fetch_author(&mut *tx, id, identity.owner.as_deref()).await?;
+
+    tx.commit()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((StatusCode::OK, Json(author)))
+}
+
+#[derive(Deserialize, Debug)]
+struct MergeRequest {
+    /// The author that's kept.
+    into: Uuid,
+}
+
+/// Moves all quotes of an author in every quote book over to another one,
+/// then deletes the former.
+async fn merge_author(
+    State(state): State<AppState>,
+    identity: Identity,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<MergeRequest>,
+) -> Result<(StatusCode, Json<Author>)> {
+    let pool = state.pool;
+
+    if identity.role != Role::Admin {
+        return Err(StatusCode::FORBIDDEN.into());
+    }
+
+    if id == payload.into {
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let owner = identity.owner.as_deref();
+    fetch_author(&mut *tx, payload.into, owner).await?;
+    fetch_author(&mut *tx, id, owner).await?;
+
+    reattribute(&mut tx, id, payload.into)
+        .await
+        .map_err(quote_update_error)?;
+    sqlx::query("DELETE FROM authors WHERE id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let author = fetch_author(&mut *tx, payload.into, owner).await?;
+
+    tx.commit()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((StatusCode::OK, Json(author)))
+}
+
+/// Attributes all quotes of the author `from`, including those in the trash, to the author `to`
+/// under their current name. Every changed quote gets a new version.
+async fn reattribute(
+    tx: &mut Transaction<'_, Postgres>,
+    from: Uuid,
+    to: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO quote_versions (quote_id, version, author, quote, owner)
+        SELECT quotes.id, quotes.version, quotes.author, quotes.quote, quotes.owner
+        FROM quotes, authors
+        WHERE quotes.author_id = $1 AND authors.id = $2
+        AND (quotes.author_id, quotes.author) <> (authors.id, authors.name)
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(from)
+    .bind(to)
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query(
+        "UPDATE quotes SET author_id = authors.id, author = authors.name, version = version + 1
+        FROM authors
+        WHERE quotes.author_id = $1 AND authors.id = $2
+        AND (quotes.author_id, quotes.author) <> (authors.id, authors.name)",
+    )
+    .bind(from)
+    .bind(to)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Formats quotes can be imported from and exported in.
+#[derive(Clone, Copy, Debug)]
+enum QuoteFormat {
+    /// A single JSON array.
+    Json,
+    /// CSV with a header row.
+    Csv,
+    /// One JSON object per line.
+    Ndjson,
+}
+
+impl QuoteFormat {
+    fn from_mime(mime: &str) -> Option<Self> {
+        match mime.split(';').next()?.trim() {
+            "application/json" => Some(Self::Json),
+            "text/csv" => Some(Self::Csv),
+            "application/x-ndjson" | "application/ndjson" => Some(Self::Ndjson),
+            _ => None,
+        }
+    }
+
+    fn mime(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Csv => "text/csv",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    /// Parses every row of `body` on its own, so that one bad row doesn't hide the others.
+    /// Rows are numbered from 1, and for NDJSON they're line numbers.
+    fn parse(self, body: &[u8]) -> Result<Vec<(usize, Result<DraftRequest, String>)>> {
+        let rows = match self {
+            Self::Json => serde_json::from_slice::<Vec<serde_json::Value>>(body)
+                .map_err(|_| StatusCode::BAD_REQUEST)?
+                .into_iter()
+                .map(|row| serde_json::from_value(row).map_err(|e| e.to_string()))
+                .enumerate()
+                .map(|(i, row)| (i + 1, row))
+                .collect(),
+            Self::Csv => csv::Reader::from_reader(body)
+                .deserialize()
+                .map(|row| row.map_err(|e| e.to_string()))
+                .enumerate()
+                .map(|(i, row)| (i + 1, row))
+                .collect(),
+            Self::Ndjson => body
+                .split(|&b| b == b'\n')
+                .enumerate()
+                .filter(|(_, line)| !line.trim_ascii().is_empty())
+                .map(|(i, line)| {
+                    let row = serde_json::from_slice(line).map_err(|e| e.to_string());
+                    (i + 1, row)
+                })
+                .collect(),
+        };
+
+        Ok(rows)
+    }
+
+    /// What goes before the first quote of an export.
+    fn prelude(self) -> &'static str {
+        match self {
+            Self::Json => "[",
+            Self::Csv => "id,author,author_id,quote,created_at,version\n",
+            Self::Ndjson => "",
+        }
+    }
+
+    /// What goes after the last quote of an export.
+    fn epilogue(self) -> &'static str {
+        match self {
+            Self::Json => "]",
+            Self::Csv | Self::Ndjson => "",
+        }
+    }
+
+    fn encode(self, quote: &Quote, first: bool) -> Vec<u8> {
+        match self {
+            Self::Json => {
+                let mut chunk = if first { vec![] } else { vec![b','] };
+                serde_json::to_writer(&mut chunk, quote).expect("quotes should serialize");
+                chunk
+            }
+            Self::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(vec![]);
+                writer.serialize(quote).expect("quotes should serialize");
+                writer.into_inner().expect("writing to a Vec can't fail")
+            }
+            Self::Ndjson => {
+                let mut chunk = serde_json::to_vec(quote).expect("quotes should serialize");
+                chunk.push(b'\n');
+                chunk
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct RowError {
+    row: usize,
+    error: String,
+    /// What's wrong with each field, if the row could be read but isn't a valid quote.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    fields: BTreeMap<&'static str, Vec<String>>,
+}
+
+#[derive(Serialize, Debug)]
+struct ImportReport {
+    imported: usize,
+    errors: Vec<RowError>,
+}
+
+/// Imports quotes in bulk. Either every row is imported or, if any of them is invalid, none are.
+async fn import(
+    State(state): State<AppState>,
+    identity: Identity,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, Json<ImportReport>)> {
+    let pool = state.pool;
+
+    let format = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(QuoteFormat::from_mime)
+        .ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
+
+    let mut drafts = Vec::new();
+    let mut errors = Vec::new();
+    for (row, draft) in format.parse(&body)? {
+        match draft.map(|draft| validate_quote(&draft.author, &draft.quote)) {
+            Ok(Ok((author, quote))) => drafts.push(DraftRequest { author, quote }),
+            Ok(Err(invalid)) => errors.push(RowError {
+                row,
+                error: "invalid quote".to_string(),
+                fields: invalid.errors,
+            }),
+            Err(error) => errors.push(RowError {
+                row,
+                error,
+                fields: BTreeMap::new(),
+            }),
+        }
+    }
+
+    if !errors.is_empty() {
+        let report = ImportReport {
+            imported: 0,
+            errors,
+        };
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, Json(report)));
+    }
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    for draft in &drafts {
+        let (author_id, author) = upsert_author(&mut tx, &draft.author)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        let quote: Quote = query_as(
+            "INSERT INTO quotes (id, author, author_id, quote, owner) VALUES ($1, $2, $3, $4, $5)
+            RETURNING *",
+        )
+        .bind(Uuid::new_v4())
+        .bind(author)
+        .bind(author_id)
+        .bind(&draft.quote)
+        .bind(identity.owner.as_deref())
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(quote_save_error)?;
+
+        // Every imported quote is a draft like any other, as far as webhooks are concerned.
+        enqueue_quote_webhooks(
+            &mut tx,
+            WebhookEvent::Draft,
+            &quote,
+            identity.owner.as_deref(),
+        )
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+
+    tx.commit()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    state.webhooks.notify_one();
+
+    let report = ImportReport {
+        imported: drafts.len(),
+        errors,
+    };
+
+    Ok((StatusCode::CREATED, Json(report)))
+}
+
+/// Streams every quote of the own quote book in the format asked for in the `Accept` header, JSON by default.
+async fn export(
+    State(state): State<AppState>,
+    identity: Identity,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    let pool = state.pool;
+
+    let offered = [
+        "application/json",
+        "text/csv",
+        "application/x-ndjson",
+        "application/ndjson",
+    ];
+    let format = negotiate::preferred(&headers, &offered)
+        .and_then(QuoteFormat::from_mime)
+        .ok_or(StatusCode::NOT_ACCEPTABLE)?;
+
+    // Quotes are sent as soon as they're fetched, so the table never has to fit in memory.
+    let (tx, rx) = mpsc::channel::<Result<Vec<u8>, sqlx::Error>>(64);
+    tokio::spawn(async move {
+        if tx.send(Ok(format.prelude().into())).await.is_err() {
+            return;
+        }
+
+        let mut quotes = query_as::<_, Quote>(
+            "SELECT * FROM quotes
+            WHERE owner IS NOT DISTINCT FROM $1 AND deleted_at IS NULL
+            ORDER BY created_at ASC, id ASC",
+        )
+        .bind(identity.owner)
+        .fetch(&*pool);
+
+        let mut first = true;
+        while let Some(quote) = quotes.next().await {
+            let chunk = quote.map(|quote| format.encode(&quote, first));
+            let failed = chunk.is_err();
+            // Stop early if the client went away or the query failed.
+            if tx.send(chunk).await.is_err() || failed {
+                return;
+            }
+            first = false;
+        }
+
+        let _ = tx.send(Ok(format.epilogue().into())).await;
+    });
+
+    Ok((
+        [(header::CONTENT_TYPE, format.mime())],
+        Body::from_stream(ReceiverStream::new(rx)),
+    ))
+}
+
+/// A quote that got in before quotes were validated, and breaks some of the rules now.
+#[derive(FromRow, Serialize, Debug)]
+struct Violation {
+    id: Uuid,
+    owner: Option<String>,
+    /// Names of the violated `CHECK` constraints.
+    violations: Vec<String>,
+}
+
+/// Finds quotes in every quote book that violate the constraints on `quotes`.
+async fn audit(
+    State(state): State<AppState>,
+    identity: Identity,
+) -> Result<(StatusCode, Json<Vec<Violation>>)> {
+    let pool = state.pool;
+
+    if identity.role != Role::Admin {
+        return Err(StatusCode::FORBIDDEN.into());
+    }
+
+    let violations: Vec<Violation> = query_as("SELECT * FROM quote_violations ORDER BY id ASC")
+        .fetch_all(&*pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((StatusCode::OK, Json(violations)))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+enum WebhookEvent {
+    Draft,
+    Undo,
+    Remove,
+    Reset,
+}
+
+/// Queues `payload` for every webhook subscribed to `event` in the quote book of `owner`,
+/// or in every quote book if `all`. Nothing is delivered unless `tx` is committed.
+async fn enqueue_webhooks(
+    tx: &mut Transaction<'_, Postgres>,
+    owner: Option<&str>,
+    all: bool,
+    event: WebhookEvent,
+    payload: &serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO webhook_deliveries (subscription_id, event, payload)
+        SELECT id, $1, $2 FROM webhook_subscriptions
+        WHERE $1 = ANY(events) AND ($3 OR owner = $4)",
+    )
+    .bind(event)
+    .bind(payload)
+    .bind(all)
+    .bind(owner)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Queues an event about a single quote.
+async fn enqueue_quote_webhooks(
+    tx: &mut Transaction<'_, Postgres>,
+    event: WebhookEvent,
+    quote: &Quote,
+    owner: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let payload = json!({
+        "event": event,
+        "occurred_at": Utc::now(),
+        "quote": quote,
+    });
+
+    enqueue_webhooks(tx, owner, false, event, &payload).await
+}
+
+#[derive(FromRow, Serialize, Debug)]
+struct Subscription {
+    id: Uuid,
+    url: String,
+    events: Vec<WebhookEvent>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SubscriptionRequest {
+    url: String,
+    events: Vec<WebhookEvent>,
+    /// Generated if not given.
+    secret: Option<String>,
+}
+
+/// A new subscription, which is the only time its secret is shown.
+#[derive(Serialize, Debug)]
+struct NewSubscription {
+    #[serde(flatten)]
+    subscription: Subscription,
+    secret: String,
+}
+
+/// Whether webhooks may be sent to `ip`. Loopback, private, link-local and other addresses that
+/// aren't reachable over the internet are refused, so webhooks can't be pointed at services that
+/// are only meant to be reached from the machine or network the server runs on.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // "This network" and the shared address space carrier-grade NATs use.
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(ip.into()),
+            None => {
+                let first = ip.segments()[0];
+
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_multicast()
+                    // Unique local and link-local addresses.
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Checks that webhooks may be sent to `url`, resolving its host if it's a name.
+///
+/// Returns the parsed URL and the addresses its host resolved to, if it had to be resolved.
+/// Deliveries connect to those rather than resolving the host again, as it could resolve to
+/// something else by then.
+async fn check_webhook_url(
+    url: &str,
+    allow_private: bool,
+) -> Result<(Url, Vec<SocketAddr>), String> {
+    let url = Url::parse(url)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .ok_or("must be an http or https URL")?;
+    if allow_private {
+        return Ok((url, Vec::new()));
+    }
+
+    let host = url.host_str().ok_or("must have a host")?;
+    let (ips, addresses) = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => (vec![ip], Vec::new()),
+        Err(_) => {
+            let addresses: Vec<SocketAddr> =
+                lookup_host((host, url.port_or_known_default().unwrap_or(80)))
+                    .await
+                    .map_err(|_| "must have a host that can be resolved")?
+                    .collect();
+            (addresses.iter().map(SocketAddr::ip).collect(), addresses)
+        }
+    };
+    if ips.is_empty() || !ips.into_iter().all(is_public) {
+        return Err("must not point at a loopback, private or link-local address".to_string());
+    }
+
+    Ok((url, addresses))
+}
+
+/// Subscribes a webhook to changes in the own quote book, which takes an owner.
+///
+/// Payloads are signed with HMAC-SHA256, keyed with the secret of the subscription,
+/// over the `X-Webhook-Timestamp` header, a `.` and the body. The signature is sent as
+/// `X-Webhook-Signature: sha256=<hex>`.
+async fn subscribe(
+    State(state): State<AppState>,
+    identity: Identity,
+    Json(payload): Json<SubscriptionRequest>,
+) -> Result<(StatusCode, Json<NewSubscription>)> {
+    let pool = state.pool;
+
+    let Some(owner) = identity.owner else {
+        return Err(StatusCode::UNAUTHORIZED.into());
+    };
+
+    let mut errors = FieldErrors::default();
+    if let Err(error) = check_webhook_url(&payload.url, state.allow_private_webhooks).await {
+        errors.add("url", error);
+    }
+    if payload.events.is_empty() {
+        errors.add("events", "must not be empty".to_string());
+    }
+    if payload.secret.as_deref().is_some_and(str::is_empty) {
+        errors.add("secret", "must not be empty".to_string());
+    }
+    if !errors.errors.is_empty() {
+        return Err(errors.into());
+    }
+
+    let secret = payload.secret.unwrap_or_else(|| {
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect()
+    });
+
+    let subscription: Subscription = query_as(
+        "INSERT INTO webhook_subscriptions (owner, url, secret, events) VALUES ($1, $2, $3, $4)
+        RETURNING *",
+    )
+    .bind(owner)
+    .bind(payload.url)
+    .bind(&secret)
+    .bind(payload.events)
+    .fetch_one(&*pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(NewSubscription {
+            subscription,
+            secret,
+        }),
+    ))
+}
+
+async fn webhooks(
+    State(state): State<AppState>,
+    identity: Identity,
+) -> Result<(StatusCode, Json<Vec<Subscription>>)> {
+    let pool = state.pool;
+
+    let subscriptions: Vec<Subscription> = query_as(
+        "SELECT * FROM webhook_subscriptions
+        WHERE owner = $1
+        ORDER BY created_at ASC",
+    )
+    .bind(identity.owner)
+    .fetch_all(&*pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((StatusCode::OK, Json(subscriptions)))
+}
+
+/// Deletes a subscription, along with its delivery log.
+async fn unsubscribe(
+    State(state): State<AppState>,
+    identity: Identity,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode> {
+    let pool = state.pool;
+
+    let deleted = sqlx::query("DELETE FROM webhook_subscriptions WHERE id = $1 AND owner = $2")
+        .bind(id)
+        .bind(identity.owner)
+        .execute(&*pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if deleted.rows_affected() == 0 {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(FromRow, Serialize, Debug)]
+struct Delivery {
+    id: Uuid,
+    event: WebhookEvent,
+    payload: serde_json::Value,
+    /// `pending`, `delivered` or `failed`, once it's been given up on.
+    status: String,
+    attempts: i32,
+    next_attempt_at: DateTime<Utc>,
+    last_status_code: Option<i32>,
+    last_error: Option<String>,
+    created_at: DateTime<Utc>,
+    delivered_at: Option<DateTime<Utc>>,
+}
+
+/// The latest deliveries to a webhook, newest first.
+async fn deliveries(
+    State(state): State<AppState>,
+    identity: Identity,
+    Path(id): Path<Uuid>,
+) -> Result<(StatusCode, Json<Vec<Delivery>>)> {
+    let pool = state.pool;
+
+    let exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS (
+            SELECT 1 FROM webhook_subscriptions WHERE id = $1 AND owner = $2
+        )",
+    )
+    .bind(id)
+    .bind(identity.owner)
+    .fetch_one(&*pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !exists {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+
+    let deliveries: Vec<Delivery> = query_as(
+        "SELECT * FROM webhook_deliveries
+        WHERE subscription_id = $1
+        ORDER BY created_at DESC
+        LIMIT 100",
+    )
+    .bind(id)
+    .fetch_all(&*pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((StatusCode::OK, Json(deliveries)))
+}
+
+/// A delivery that's due, along with where it goes.
+#[derive(FromRow, Debug)]
+struct DueDelivery {
+    id: Uuid,
+    payload: serde_json::Value,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+/// Delivers queued webhook payloads, whenever new ones are queued and whenever retries come due.
+///
+/// Deliveries are claimed before they're attempted, so that every instance can run this without
+/// sending anything twice. A claim expires if its instance dies before recording the outcome.
+async fn deliver_webhooks(pool: Arc<PgPool>, wake: Arc<Notify>, allow_private: bool) {
+    loop {
+        let due: Result<Vec<DueDelivery>, _> = query_as(
+            "WITH claimed AS (
+                SELECT id FROM webhook_deliveries
+                WHERE status = 'pending' AND next_attempt_at <= CURRENT_TIMESTAMP
+                ORDER BY next_attempt_at ASC
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            UPDATE webhook_deliveries
+            SET attempts = attempts + 1,
+                next_attempt_at = CURRENT_TIMESTAMP + make_interval(secs => $2)
+            FROM claimed, webhook_subscriptions
+            WHERE webhook_deliveries.id = claimed.id
+                AND webhook_subscriptions.id = webhook_deliveries.subscription_id
+            RETURNING webhook_deliveries.id, webhook_deliveries.payload, webhook_deliveries.attempts,
+                webhook_subscriptions.url, webhook_subscriptions.secret",
+        )
+        .bind(WEBHOOK_BATCH_SIZE)
+        .bind(2.0 * WEBHOOK_TIMEOUT.as_secs_f64())
+        .fetch_all(&*pool)
+        .await;
+
+        match due {
+            Ok(due) => {
+                let more = due.len() as i64 == WEBHOOK_BATCH_SIZE;
+                for delivery in due {
+                    tokio::spawn(deliver(pool.clone(), delivery, allow_private));
+                }
+                if more {
+                    continue;
+                }
+            }
+            Err(e) => event!(
+                Level::WARN,
+                "Looking for due webhook deliveries failed: {e}"
+            ),
+        }
+
+        tokio::select! {
+            _ = wake.notified() => {}
+            _ = time::sleep(WEBHOOK_POLL_INTERVAL) => {}
+        }
+    }
+}
+
+/// Sends a delivery to its receiver, returning the status code it responded with, if it did,
+/// and what went wrong, if anything.
+async fn send(delivery: &DueDelivery, allow_private: bool) -> (Option<i32>, Option<String>) {
+    // Hosts may resolve to something else than when the subscription was made,
+    // and subscriptions made before URLs were checked at all may point anywhere.
+    let (url, addresses) = match check_webhook_url(&delivery.url, allow_private).await {
+        Ok(checked) => checked,
+        Err(error) => return (None, Some(format!("URL {error}"))),
+    };
+
+    // Redirects aren't followed, since they could lead anywhere.
+    let mut client = reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .redirect(redirect::Policy::none());
+    if let Some(host) = url.host_str().filter(|_| !addresses.is_empty()) {
+        client = client.resolve_to_addrs(host, &addresses);
+    }
+    let client = client.build().expect("HTTP client should build");
+
+    let body = serde_json::to_vec(&delivery.payload).expect("JSON values should serialize");
+    let timestamp = Utc::now().timestamp();
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(delivery.secret.as_bytes())
+        .expect("HMAC should take keys of any length");
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(&body);
+    let signature = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        });
+
+    let response = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .header("X-Webhook-Id", delivery.id.to_string())
+        .header("X-Webhook-Timestamp", timestamp.to_string())
+        .header("X-Webhook-Signature", format!("sha256={signature}"))
+        .body(body)
+        .send()
+        .await;
+
+    match response {
+        Ok(response) if response.status().is_success() => {
+            (Some(i32::from(response.status().as_u16())), None)
+        }
+        Ok(response) => (
+            Some(i32::from(response.status().as_u16())),
+            Some(format!("Receiver responded with {}", response.status())),
+        ),
+        Err(e) => (None, Some(e.to_string())),
+    }
+}
+
+/// Makes one attempt at a delivery and records how it went.
+async fn deliver(pool: Arc<PgPool>, delivery: DueDelivery, allow_private: bool) {
+    let (status_code, error) = send(&delivery, allow_private).await;
+
+    let recorded = match error {
+        None => {
+            sqlx::query(
+                "UPDATE webhook_deliveries
+            SET status = 'delivered', delivered_at = CURRENT_TIMESTAMP,
+                last_status_code = $2, last_error = NULL
+            WHERE id = $1",
+            )
+            .bind(delivery.id)
+            .bind(status_code)
+            .execute(&*pool)
+            .await
+        }
+        Some(error) => {
+            let retries = u32::try_from(delivery.attempts - 1).unwrap_or(0).min(31);
+            let delay = WEBHOOK_RETRY_DELAY
+                .saturating_mul(1 << retries)
+                .min(MAX_WEBHOOK_RETRY_DELAY);
+
+            sqlx::query(
+                "UPDATE webhook_deliveries
+                SET status = CASE WHEN attempts >= $4 THEN 'failed' ELSE 'pending' END,
+                    next_attempt_at = CURRENT_TIMESTAMP + make_interval(secs => $5),
+                    last_status_code = $2, last_error = $3
+                WHERE id = $1",
+            )
+            .bind(delivery.id)
+            .bind(status_code)
+            .bind(error)
+            .bind(MAX_WEBHOOK_ATTEMPTS)
+            .bind(delay.as_secs_f64())
+            .execute(&*pool)
+            .await
+        }
+    };
+
+    if let Err(e) = recorded {
+        event!(Level::WARN, "Recording a webhook delivery failed: {e}");
+    }
+}
+
+/// Periodically deletes quotes, along with their history,
+/// that have been in the trash for longer than `retention`.
+async fn purge_trash(pool: Arc<PgPool>, retention: Duration) {
+    let mut interval = time::interval(TRASH_PURGE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let purged = sqlx::query(
+            "WITH purged AS (
+                DELETE FROM quotes
+                WHERE deleted_at < CURRENT_TIMESTAMP - make_interval(secs => $1)
+                RETURNING id
+            )
+            DELETE FROM quote_versions WHERE quote_id IN (SELECT id FROM purged)",
+        )
+        .bind(retention.as_secs_f64())
+        .execute(&*pool)
+        .await;
+
+        if let Err(e) = purged {
+            event!(Level::WARN, "Purging the quote trash failed: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// A connection to the database in `DATABASE_URL`, if it's set. Tests that need one pass
+    /// without doing anything otherwise.
+    async fn database() -> Option<PgPool> {
+        let url = std::env::var("DATABASE_URL").ok()?;
+        let pool = PgPool::connect(&url)
+            .await
+            .expect("database should be reachable");
+        sqlx::migrate!()
+            .run(&pool)
+            .await
+            .expect("migrations should run");
+
+        Some(pool)
+    }
+
+    fn state(pool: PgPool) -> AppState {
+        AppState {
+            pool: Arc::new(pool),
+            cursor_ttl: DEFAULT_CURSOR_TTL,
+            cursor_secret: secret("QUOTE_CURSOR_SECRET"),
+            token_secret: secret("QUOTE_TOKEN_SECRET"),
+            token_ttl: DEFAULT_TOKEN_TTL,
+            webhooks: Arc::new(Notify::new()),
+            allow_private_webhooks: false,
+        }
+    }
+
+    #[test]
+    fn only_public_addresses_are_public() {
+        for ip in ["93.184.215.14", "1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn webhook_urls_have_to_be_public() {
+        for url in [
+            "http://127.0.0.1:9999/hook",
+            "http://[::1]/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "https://10.0.0.1/",
+            "http://localhost:8000/",
+        ] {
+            assert!(check_webhook_url(url, false).await.is_err(), "{url}");
+            assert!(check_webhook_url(url, true).await.is_ok(), "{url}");
+        }
+        for url in ["ftp://93.184.215.14/", "not a url"] {
+            assert!(check_webhook_url(url, true).await.is_err(), "{url}");
+        }
+
+        let (_, addresses) = check_webhook_url("https://93.184.215.14/hook", false)
+            .await
+            .unwrap();
+        assert!(addresses.is_empty());
+    }
+
+    /// Accepts deliveries signed with `secret`, passing on their payloads.
+    async fn receiver(secret: &'static str) -> (SocketAddr, mpsc::Receiver<serde_json::Value>) {
+        let (tx, rx) = mpsc::channel(8);
+        let app = Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: Bytes| async move {
+                let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+                let (Some(timestamp), Some(signature)) =
+                    (header("X-Webhook-Timestamp"), header("X-Webhook-Signature"))
+                else {
+                    return StatusCode::BAD_REQUEST;
+                };
+
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+                mac.update(format!("{timestamp}.").as_bytes());
+                mac.update(&body);
+                let expected = mac.finalize().into_bytes().iter().fold(
+                    "sha256=".to_string(),
+                    |mut hex, byte| {
+                        let _ = write!(hex, "{byte:02x}");
+                        hex
+                    },
+                );
+                if signature != expected {
+                    return StatusCode::UNAUTHORIZED;
+                }
+
+                let _ = tx.send(serde_json::from_slice(&body).unwrap()).await;
+                StatusCode::NO_CONTENT
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        (address, rx)
+    }
+
+    #[tokio::test]
+    async fn deliveries_are_signed() {
+        let (address, mut received) = receiver("topsecret").await;
+        let payload = json!({ "event": WebhookEvent::Draft, "quote": { "quote": "Hi" } });
+        let delivery = |url: String, secret: &str| DueDelivery {
+            id: Uuid::new_v4(),
+            payload: payload.clone(),
+            attempts: 1,
+            url,
+            secret: secret.to_string(),
+        };
+
+        let url = format!("http://{address}/hook");
+        assert_eq!(
+            send(&delivery(url.clone(), "topsecret"), true).await,
+            (Some(204), None)
+        );
+        assert_eq!(received.recv().await, Some(payload.clone()));
+
+        let (status_code, error) = send(&delivery(url.clone(), "wrong"), true).await;
+        assert_eq!(status_code, Some(401));
+        assert!(error.is_some());
+
+        // Without opting in, the receiver can't be reached at all, by address or by name.
+        let by_name = format!("http://localhost:{}/hook", address.port());
+        for url in [url, by_name] {
+            let (status_code, error) = send(&delivery(url, "topsecret"), false).await;
+            assert_eq!(status_code, None);
+            assert!(error.is_some());
+        }
+        assert!(received.try_recv().is_err());
+    }
+
+    /// How often each quote of `owner` comes up in `times` random picks.
+    async fn draw(
+        state: &AppState,
+        owner: &str,
+        tag: Option<&str>,
+        times: usize,
+    ) -> HashMap<Uuid, usize> {
+        let mut counts = HashMap::new();
+        for _ in 0..times {
+            let identity = Identity {
+                owner: Some(owner.to_owned()),
+                ..Default::default()
+            };
+            let params = RandomParams {
+                author: None,
+                tag: tag.map(str::to_owned),
+            };
+            let (_, Json(quote)) = random(State(state.clone()), identity, Query(params))
+                .await
+                .unwrap();
+            *counts.entry(quote.id).or_default() += 1;
+        }
+
+        counts
+    }
+
+    #[tokio::test]
+    async fn random_quotes_are_picked_evenly() {
+        let Some(pool) = database().await else {
+            return;
+        };
+        let state = state(pool.clone());
+        let owner = format!("random-{}", Uuid::new_v4());
+
+        let mut tx = pool.begin().await.unwrap();
+        let (author_id, author) = upsert_author(&mut tx, "Random Tester").await.unwrap();
+        let mut ids = Vec::new();
+        for i in 0..5 {
+            let id: Uuid = sqlx::query_scalar(
+                "INSERT INTO quotes (id, author, author_id, quote, owner)
+                VALUES (gen_random_uuid(), $1, $2, $3, $4) RETURNING id",
+            )
+            .bind(&author)
+            .bind(author_id)
+            .bind(format!("Quote {i}"))
+            .bind(&owner)
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+            ids.push(id);
+        }
+        sqlx::query("INSERT INTO quote_tags (quote_id, tag) SELECT unnest($1::UUID[]), 'even'")
+            .bind(&ids[..3])
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        // Each of these is more than five standard deviations from the expected 400.
+        let all = draw(&state, &owner, None, 2000).await;
+        let tagged = draw(&state, &owner, Some("even"), 1200).await;
+
+        sqlx::query("DELETE FROM quotes WHERE owner = $1")
+            .bind(&owner)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert_eq!(all.len(), 5);
+        assert!(all.values().all(|n| (300..=500).contains(n)), "{all:?}");
+        assert!(tagged.keys().all(|id| ids[..3].contains(id)));
+        assert_eq!(tagged.len(), 3);
+        assert!(
+            tagged.values().all(|n| (300..=500).contains(n)),
+            "{tagged:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn cursors_only_work_where_they_were_issued() {
+        let state = state(PgPool::connect_lazy("postgres://localhost/unused").unwrap());
+        let now = Utc::now();
+        let exp = now.timestamp() as u64 + 60;
+
+        let list = Cursor {
+            created_at: now,
+            id: Uuid::new_v4(),
+            filter: ListFilter::default(),
+            page: 1,
+            page_size: 3,
+            exp,
+        };
+        let list = encode_cursor(&state, list).unwrap();
+        let top = TopCursor {
+            since: now,
+            until: now,
+            reactions: 1,
+            created_at: now,
+            id: Uuid::new_v4(),
             page: 1,
-            next_token: token,
+            page_size: 3,
+            exp,
         };
+        let top = encode_cursor(&state, top).unwrap();
 
-        Ok((StatusCode::OK, Json(page)))
+        assert!(decode_cursor::<Cursor>(&state, &list).is_ok());
+        assert!(decode_cursor::<TopCursor>(&state, &top).is_ok());
+        let swapped = [
+            decode_cursor::<Cursor>(&state, &top).map(drop),
+            decode_cursor::<TopCursor>(&state, &list).map(drop),
+        ];
+        for result in swapped {
+            assert_eq!(result.into_response().status(), StatusCode::BAD_REQUEST);
+        }
     }
 }
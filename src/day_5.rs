@@ -1,62 +1,239 @@
+use std::{collections::HashMap, fmt, ops::Range};
+
 use axum::{
     body::Bytes,
-    http::{HeaderMap, StatusCode},
-    response,
+    http::{header, HeaderMap, StatusCode},
+    response::{self, IntoResponse, Response},
     routing::post,
-    Router,
+    Json, Router,
 };
 use cargo_manifest::Manifest;
-use serde::Deserialize;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use toml_edit::ImDocument;
 use tracing::{event, Level};
 
+use crate::negotiate;
+
 pub fn day_five() -> Router {
-    Router::new().route("/5/manifest", post(manifest))
+    Router::new()
+        .route("/5/manifest", post(manifest))
+        .route("/5/validate", post(validate))
+        .route("/5/convert", post(convert))
 }
 
+/// The keyword a package has to list for its orders to be processed.
+static MAGIC_KEYWORD: &str = "Christmas 2024";
+
+/// Keys cargo understands at the top level of a manifest.
+static MANIFEST_KEYS: &[&str] = &[
+    "cargo-features",
+    "package",
+    "workspace",
+    "lib",
+    "bin",
+    "example",
+    "test",
+    "bench",
+    "dependencies",
+    "dev-dependencies",
+    "dev_dependencies",
+    "build-dependencies",
+    "build_dependencies",
+    "target",
+    "features",
+    "patch",
+    "replace",
+    "profile",
+    "badges",
+    "lints",
+];
+
+/// Keys cargo understands in the `package` table.
+static PACKAGE_KEYS: &[&str] = &[
+    "name",
+    "version",
+    "authors",
+    "edition",
+    "rust-version",
+    "description",
+    "documentation",
+    "readme",
+    "homepage",
+    "repository",
+    "license",
+    "license-file",
+    "keywords",
+    "categories",
+    "workspace",
+    "build",
+    "links",
+    "exclude",
+    "include",
+    "publish",
+    "metadata",
+    "default-run",
+    "autolib",
+    "autobins",
+    "autoexamples",
+    "autotests",
+    "autobenches",
+    "resolver",
+];
+
+/// Tables holding dependency specifications, both at the top level and under `target.<cfg>`.
+static DEPENDENCY_TABLES: &[&str] = &[
+    "dependencies",
+    "dev-dependencies",
+    "dev_dependencies",
+    "build-dependencies",
+    "build_dependencies",
+];
+
+/// Keys cargo understands in a detailed dependency specification.
+static DEPENDENCY_KEYS: &[&str] = &[
+    "version",
+    "path",
+    "git",
+    "branch",
+    "tag",
+    "rev",
+    "registry",
+    "registry-index",
+    "package",
+    "features",
+    "optional",
+    "default-features",
+    "default_features",
+    "workspace",
+    "public",
+    "artifact",
+    "lib",
+    "target",
+];
+
 #[derive(Deserialize, Clone)]
 struct Order {
     item: String,
     quantity: u32,
 }
 
-async fn manifest(headers: HeaderMap, body: Bytes) -> response::Result<String> {
-    event!(
-        Level::DEBUG,
-        "Request body: {}",
-        String::from_utf8(body.clone().into()).unwrap()
-    );
-
-    let content_type = headers
-        .get("Content-Type")
-        .ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?
-        .to_str()
-        .or(Err(StatusCode::UNSUPPORTED_MEDIA_TYPE))?;
+/// The formats a manifest can be posted or returned in, named by Content-Type and Accept.
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
 
-    let manifest = match content_type {
-        "application/toml" => {
-            let Ok(manifest): Result<Manifest, _> = Manifest::from_slice(&body) else {
-                return Err((StatusCode::BAD_REQUEST, "Invalid manifest").into());
-            };
+impl Format {
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "application/toml" => Some(Format::Toml),
+            "application/json" => Some(Format::Json),
+            "application/yaml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
 
-            manifest
+    fn media_type(self) -> &'static str {
+        match self {
+            Format::Toml => "application/toml",
+            Format::Json => "application/json",
+            Format::Yaml => "application/yaml",
         }
-        "application/json" => {
-            let Ok(manifest) = serde_json::from_slice(&body) else {
-                return Err((StatusCode::BAD_REQUEST, "Invalid manifest").into());
-            };
+    }
+
+    fn from_content_type(headers: &HeaderMap) -> Result<Self, StatusCode> {
+        let content_type = headers
+            .get("Content-Type")
+            .ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?
+            .to_str()
+            .or(Err(StatusCode::UNSUPPORTED_MEDIA_TYPE))?;
+
+        Format::from_media_type(content_type).ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+    }
 
-            manifest
+    /// Picks the preferred format out of the Accept header. Wildcards, and a missing header,
+    /// accept anything, in which case `fallback` is used.
+    fn from_accept(headers: &HeaderMap, fallback: Format) -> Result<Self, StatusCode> {
+        let mut offered = vec![fallback.media_type()];
+        offered.extend(
+            [Format::Toml, Format::Json, Format::Yaml]
+                .into_iter()
+                .filter(|format| *format != fallback)
+                .map(Format::media_type),
+        );
+
+        negotiate::preferred(headers, &offered)
+            .and_then(Format::from_media_type)
+            .ok_or(StatusCode::NOT_ACCEPTABLE)
+    }
+
+    fn manifest(self, body: &[u8]) -> Option<Manifest> {
+        match self {
+            Format::Toml => Manifest::from_slice(body).ok(),
+            Format::Json => serde_json::from_slice(body).ok(),
+            Format::Yaml => serde_yaml::from_slice(body).ok(),
         }
-        "application/yaml" => {
-            let Ok(manifest) = serde_yaml::from_slice(&body) else {
-                return Err((StatusCode::BAD_REQUEST, "Invalid manifest").into());
-            };
+    }
 
-            manifest
+    /// Parses a document into a format independent tree, keeping keys in document order.
+    ///
+    /// That tree is a YAML one: serde_json sorts the keys of its objects,
+    /// while YAML mappings remember the order their keys came in.
+    /// TOML is read with toml_edit for the same reason, `toml` sorts its tables.
+    fn parse(self, body: &[u8]) -> Option<serde_yaml::Value> {
+        match self {
+            Format::Toml => {
+                let document = ImDocument::parse(std::str::from_utf8(body).ok()?).ok()?;
+                let table = document.as_table().clone().into_inline_table();
+                Some(toml_to_tree(&toml_edit::Value::InlineTable(table)))
+            }
+            Format::Json => serde_json::from_slice(body).ok(),
+            Format::Yaml => serde_yaml::from_slice(body).ok(),
         }
-        _ => {
-            return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE.into());
+    }
+
+    fn render(self, value: &serde_yaml::Value) -> Result<String, String> {
+        match self {
+            Format::Toml => toml::to_string_pretty(value).map_err(|e| e.to_string()),
+            Format::Json => serde_json::to_string_pretty(value).map_err(|e| e.to_string()),
+            Format::Yaml => serde_yaml::to_string(value).map_err(|e| e.to_string()),
         }
+    }
+}
+
+/// Converts a TOML value into the tree the other formats share, see [`Format::parse`].
+/// Neither JSON nor YAML have datetimes, so those become strings.
+fn toml_to_tree(value: &toml_edit::Value) -> serde_yaml::Value {
+    match value {
+        toml_edit::Value::String(s) => s.value().as_str().into(),
+        toml_edit::Value::Integer(i) => (*i.value()).into(),
+        toml_edit::Value::Float(f) => (*f.value()).into(),
+        toml_edit::Value::Boolean(b) => (*b.value()).into(),
+        toml_edit::Value::Datetime(datetime) => datetime.value().to_string().into(),
+        toml_edit::Value::Array(values) => values.iter().map(toml_to_tree).collect(),
+        toml_edit::Value::InlineTable(table) => serde_yaml::Value::Mapping(
+            table
+                .iter()
+                .map(|(key, value)| (key.into(), toml_to_tree(value)))
+                .collect(),
+        ),
+    }
+}
+
+async fn manifest(headers: HeaderMap, body: Bytes) -> response::Result<Response> {
+    event!(
+        Level::DEBUG,
+        "Request body: {}",
+        String::from_utf8(body.clone().into()).unwrap()
+    );
+
+    let Some(manifest) = Format::from_content_type(&headers)?.manifest(&body) else {
+        return Err((StatusCode::BAD_REQUEST, "Invalid manifest").into());
     };
 
     let package = manifest.package.ok_or(StatusCode::NO_CONTENT)?;
@@ -66,18 +243,23 @@ async fn manifest(headers: HeaderMap, body: Bytes) -> response::Result<String> {
     else {
         return Err((StatusCode::BAD_REQUEST, "Magic keyword not provided").into());
     };
-    if !keywords.contains(&"Christmas 2024".to_owned()) {
+    if !keywords.iter().any(|k| k == MAGIC_KEYWORD) {
         return Err((StatusCode::BAD_REQUEST, "Magic keyword not provided").into());
     }
 
-    let mut result = String::new();
-    package
-        .metadata
-        .ok_or(StatusCode::NO_CONTENT)?
+    let metadata = package.metadata.ok_or(StatusCode::NO_CONTENT)?;
+    let orders = metadata
         .get("orders")
         .ok_or(StatusCode::NO_CONTENT)?
         .as_array()
-        .ok_or(StatusCode::NO_CONTENT)?
+        .ok_or(StatusCode::NO_CONTENT)?;
+
+    if wants_summary(&headers) {
+        return Ok(Json(OrderSummary::new(orders)).into_response());
+    }
+
+    let mut result = String::new();
+    orders
         .iter()
         .filter_map(|v| {
             let order: Option<Order> = v.clone().try_into().ok();
@@ -91,5 +273,785 @@ async fn manifest(headers: HeaderMap, body: Bytes) -> response::Result<String> {
         return Err(StatusCode::NO_CONTENT.into());
     }
 
-    Ok(result.to_owned())
+    Ok(result.to_owned().into_response())
+}
+
+/// Plain text stays the default for `/5/manifest`; the JSON summary has to be asked for by
+/// preferring `application/json` over text in the Accept header.
+fn wants_summary(headers: &HeaderMap) -> bool {
+    negotiate::preferred(headers, &["text/plain", "application/json"]) == Some("application/json")
+}
+
+/// The JSON form of `/5/manifest`: quantities added up per item in order of first
+/// appearance, and every entry that was left out with the reason why. Totals are only given
+/// when every order they cover has a unit price.
+#[derive(Serialize)]
+struct OrderSummary {
+    orders: Vec<ItemSummary>,
+    rejected: Vec<RejectedOrder>,
+    quantity: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct ItemSummary {
+    item: String,
+    quantity: u64,
+    /// Only set when every order for the item agrees on it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit_price: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct RejectedOrder {
+    index: usize,
+    entry: serde_yaml::Value,
+    reason: String,
+}
+
+/// Fields an order may carry on top of `Order`, which the plain text output ignores.
+#[derive(Deserialize)]
+struct Pricing {
+    unit_price: Option<f64>,
+}
+
+impl OrderSummary {
+    fn new(entries: &[toml::Value]) -> Self {
+        let mut orders: Vec<ItemSummary> = Vec::new();
+        let mut positions = HashMap::new();
+        let mut rejected = Vec::new();
+
+        for (index, entry) in entries.iter().enumerate() {
+            let (order, unit_price) = match Self::parse(entry) {
+                Ok(parsed) => parsed,
+                Err(reason) => {
+                    // The manifest was read with `toml`, so the entry goes through its
+                    // TOML form to be converted like everything else.
+                    let entry = entry.to_string().parse().ok();
+                    rejected.push(RejectedOrder {
+                        index,
+                        entry: entry.as_ref().map(toml_to_tree).unwrap_or_default(),
+                        reason,
+                    });
+                    continue;
+                }
+            };
+
+            let quantity = u64::from(order.quantity);
+            let total = unit_price.map(|price| cents(quantity as f64 * price));
+
+            let Some(&position) = positions.get(&order.item) else {
+                positions.insert(order.item.clone(), orders.len());
+                orders.push(ItemSummary {
+                    item: order.item,
+                    quantity,
+                    unit_price,
+                    total,
+                });
+                continue;
+            };
+
+            let summary = &mut orders[position];
+            summary.quantity += quantity;
+            if summary.unit_price != unit_price {
+                summary.unit_price = None;
+            }
+            summary.total = summary.total.zip(total).map(|(a, b)| cents(a + b));
+        }
+
+        OrderSummary {
+            quantity: orders.iter().map(|o| o.quantity).sum(),
+            total: orders
+                .iter()
+                .map(|o| o.total)
+                .sum::<Option<f64>>()
+                .filter(|_| !orders.is_empty())
+                .map(cents),
+            orders,
+            rejected,
+        }
+    }
+
+    fn parse(entry: &toml::Value) -> Result<(Order, Option<f64>), String> {
+        let order: Order = entry
+            .clone()
+            .try_into()
+            .map_err(|e| e.message().to_owned())?;
+        let pricing: Pricing = entry
+            .clone()
+            .try_into()
+            .map_err(|e| e.message().to_owned())?;
+
+        match pricing.unit_price {
+            Some(price) if !price.is_finite() || price < 0.0 => Err(format!(
+                "unit price must be a non-negative number, found {price}"
+            )),
+            unit_price => Ok((order, unit_price)),
+        }
+    }
+}
+
+/// Totals are amounts of money, so they are kept to whole cents.
+fn cents(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
+
+/// Re-encodes a manifest from its Content-Type into the format asked for in Accept. The
+/// document is carried over as a whole rather than through `Manifest`, so keys it does not
+/// know about, `package.metadata` included, survive the trip in their original order.
+async fn convert(headers: HeaderMap, body: Bytes) -> response::Result<impl IntoResponse> {
+    let source = Format::from_content_type(&headers)?;
+    let target = Format::from_accept(&headers, source)?;
+
+    if source.manifest(&body).is_none() {
+        return Err((StatusCode::BAD_REQUEST, "Invalid manifest").into());
+    }
+
+    let converted = if source == target {
+        body
+    } else {
+        let value = source
+            .parse(&body)
+            .ok_or((StatusCode::BAD_REQUEST, "Invalid manifest"))?;
+        let converted = target.render(&value).map_err(|e| {
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Manifest cannot be written as {}: {e}", target.media_type()),
+            )
+        })?;
+
+        converted.into()
+    };
+
+    Ok(([(header::CONTENT_TYPE, target.media_type())], converted))
+}
+
+/// Every problem found in a manifest. It is valid when none of them is an error.
+#[derive(Serialize)]
+struct Report {
+    valid: bool,
+    format: Format,
+    problems: Vec<Problem>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Serialize)]
+struct Problem {
+    severity: Severity,
+    path: String,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    column: Option<usize>,
+}
+
+/// A location inside a manifest, written like `package.metadata.orders[1].quantity`.
+#[derive(Clone, Default)]
+struct Path(Vec<Segment>);
+
+#[derive(Clone)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+impl Path {
+    fn key(&self, key: &str) -> Path {
+        let mut path = self.clone();
+        path.0.push(Segment::Key(key.to_owned()));
+        path
+    }
+
+    fn index(&self, index: usize) -> Path {
+        let mut path = self.clone();
+        path.0.push(Segment::Index(index));
+        path
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str(".");
+        }
+
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                Segment::Key(key) if i == 0 => f.write_str(key)?,
+                Segment::Key(key) => write!(f, ".{key}")?,
+                Segment::Index(index) => write!(f, "[{index}]")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn validate(headers: HeaderMap, body: Bytes) -> response::Result<Json<Report>> {
+    let format = Format::from_content_type(&headers)?;
+
+    let mut validator = Validator::new(format, &body);
+    if let Some(manifest) = validator.parse() {
+        validator.check_manifest(&manifest);
+        validator.deserialize();
+    }
+
+    Ok(Json(validator.finish()))
+}
+
+/// Walks a manifest collecting problems. TOML sources are kept around so problems can be
+/// given a line and column; JSON and YAML only get one for syntax errors.
+struct Validator<'a> {
+    format: Format,
+    body: &'a [u8],
+    source: Option<&'a str>,
+    document: Option<ImDocument<&'a str>>,
+    problems: Vec<Problem>,
+}
+
+impl<'a> Validator<'a> {
+    fn new(format: Format, body: &'a [u8]) -> Self {
+        let source = std::str::from_utf8(body).ok();
+        let document = match format {
+            Format::Toml => source.and_then(|source| ImDocument::parse(source).ok()),
+            Format::Json | Format::Yaml => None,
+        };
+
+        Validator {
+            format,
+            body,
+            source,
+            document,
+            problems: Vec::new(),
+        }
+    }
+
+    fn finish(mut self) -> Report {
+        self.problems
+            .sort_by_key(|p| (p.line.is_none(), p.line, p.column));
+
+        Report {
+            valid: self.problems.iter().all(|p| p.severity != Severity::Error),
+            format: self.format,
+            problems: self.problems,
+        }
+    }
+
+    /// Parses the body into a format independent tree, reporting syntax errors.
+    fn parse(&mut self) -> Option<Value> {
+        let result = match self.format {
+            Format::Toml => {
+                let Some(source) = self.source else {
+                    let error = std::str::from_utf8(self.body).unwrap_err();
+                    let valid = &self.body[..error.valid_up_to()];
+                    let location = std::str::from_utf8(valid)
+                        .ok()
+                        .and_then(|valid| line_column(valid, valid.len()));
+                    self.push(Severity::Error, ".".to_owned(), error.to_string(), location);
+                    return None;
+                };
+
+                toml::from_str(source).map_err(|e| {
+                    let location = e.span().and_then(|span| self.locate(span.start));
+                    (e.message().to_owned(), location)
+                })
+            }
+            Format::Json => serde_json::from_slice(self.body).map_err(|e| {
+                let location = (e.line() > 0).then(|| (e.line(), e.column()));
+                (without_location(e.to_string()), location)
+            }),
+            Format::Yaml => serde_yaml::from_slice(self.body).map_err(|e| {
+                let location = e.location().map(|l| (l.line(), l.column()));
+                (without_location(e.to_string()), location)
+            }),
+        };
+
+        match result {
+            Ok(value) => Some(value),
+            Err((message, location)) => {
+                self.push(Severity::Error, ".".to_owned(), message, location);
+                None
+            }
+        }
+    }
+
+    /// Deserializes the body into a `Manifest` like `/5/manifest` does, catching whatever
+    /// cargo_manifest rejects that the checks did not already report.
+    fn deserialize(&mut self) {
+        let (path, message, location) = match self.format {
+            Format::Toml => {
+                let Some(source) = self.source else {
+                    return;
+                };
+                let Err(e) = serde_path_to_error::deserialize::<_, Manifest>(
+                    toml::Deserializer::new(source),
+                ) else {
+                    return;
+                };
+                let location = e.inner().span().and_then(|span| self.locate(span.start));
+                (
+                    e.path().to_string(),
+                    e.inner().message().to_owned(),
+                    location,
+                )
+            }
+            Format::Json => {
+                let mut deserializer = serde_json::Deserializer::from_slice(self.body);
+                let Err(e) = serde_path_to_error::deserialize::<_, Manifest>(&mut deserializer)
+                else {
+                    return;
+                };
+                let location =
+                    (e.inner().line() > 0).then(|| (e.inner().line(), e.inner().column()));
+                (
+                    e.path().to_string(),
+                    without_location(e.inner().to_string()),
+                    location,
+                )
+            }
+            Format::Yaml => {
+                let Err(e) = serde_path_to_error::deserialize::<_, Manifest>(
+                    serde_yaml::Deserializer::from_slice(self.body),
+                ) else {
+                    return;
+                };
+                let location = e.inner().location().map(|l| (l.line(), l.column()));
+                (
+                    e.path().to_string(),
+                    without_location(e.inner().to_string()),
+                    location,
+                )
+            }
+        };
+
+        if self.problems.iter().any(|p| overlaps(&p.path, &path)) {
+            return;
+        }
+
+        self.push(Severity::Error, path, message, location);
+    }
+
+    fn check_manifest(&mut self, manifest: &Value) {
+        let root = Path::default();
+        let Some(manifest) = self.table(&root, manifest) else {
+            return;
+        };
+
+        self.unknown_keys(&root, manifest, MANIFEST_KEYS);
+
+        match manifest.get("package") {
+            Some(package) => self.check_package(&root.key("package"), package),
+            None if manifest.contains_key("workspace") => {}
+            None => self.error(&root.key("package"), "missing `package` table"),
+        }
+
+        self.check_dependency_tables(&root, manifest);
+
+        if let Some(targets) = manifest.get("target") {
+            let path = root.key("target");
+            if let Some(targets) = self.table(&path, targets) {
+                for (cfg, target) in targets {
+                    let path = path.key(cfg);
+                    if let Some(target) = self.table(&path, target) {
+                        self.check_dependency_tables(&path, target);
+                    }
+                }
+            }
+        }
+
+        if let Some(dependencies) = manifest
+            .get("workspace")
+            .and_then(|workspace| workspace.get("dependencies"))
+        {
+            self.check_dependencies(&root.key("workspace").key("dependencies"), dependencies);
+        }
+    }
+
+    fn check_package(&mut self, path: &Path, package: &Value) {
+        let Some(package) = self.table(path, package) else {
+            return;
+        };
+
+        self.unknown_keys(path, package, PACKAGE_KEYS);
+
+        let name = path.key("name");
+        match package.get("name") {
+            None => self.error(&name, "missing required field `name`"),
+            Some(value) => {
+                if let Some(value) = self.string(&name, value) {
+                    if value.is_empty() {
+                        self.error(&name, "package name cannot be empty");
+                    } else if let Some(c) = value
+                        .chars()
+                        .find(|c| !(c.is_alphanumeric() || *c == '-' || *c == '_'))
+                    {
+                        self.error(&name, format!("invalid character `{c}` in package name"));
+                    }
+                }
+            }
+        }
+
+        let version = path.key("version");
+        match package.get("version") {
+            None => self.warning(
+                &version,
+                "missing field `version`, cargo defaults it to 0.0.0",
+            ),
+            Some(value) if is_inherited(value) => {}
+            Some(value) => {
+                if let Some(value) = self.string(&version, value) {
+                    if let Err(e) = Version::parse(value) {
+                        self.error(&version, format!("invalid version `{value}`: {e}"));
+                    }
+                }
+            }
+        }
+
+        let keywords = path.key("keywords");
+        match package.get("keywords") {
+            None => self.error(
+                &keywords,
+                format!("missing field `keywords`, magic keyword \"{MAGIC_KEYWORD}\" not provided"),
+            ),
+            Some(value) if is_inherited(value) => self.error(
+                &keywords,
+                format!("inherited keywords cannot provide magic keyword \"{MAGIC_KEYWORD}\""),
+            ),
+            Some(value) => {
+                if let Some(values) = self.array(&keywords, value) {
+                    let mut magic = false;
+                    for (i, value) in values.iter().enumerate() {
+                        magic |= self.string(&keywords.index(i), value) == Some(MAGIC_KEYWORD);
+                    }
+                    if !magic {
+                        self.error(
+                            &keywords,
+                            format!("magic keyword \"{MAGIC_KEYWORD}\" not provided"),
+                        );
+                    }
+                }
+            }
+        }
+
+        if let Some(orders) = package
+            .get("metadata")
+            .and_then(|metadata| metadata.get("orders"))
+        {
+            self.check_orders(&path.key("metadata").key("orders"), orders);
+        }
+    }
+
+    fn check_orders(&mut self, path: &Path, orders: &Value) {
+        let Some(orders) = self.array(path, orders) else {
+            return;
+        };
+
+        for (i, order) in orders.iter().enumerate() {
+            let path = path.index(i);
+            let Some(order) = self.table(&path, order) else {
+                continue;
+            };
+
+            let item = path.key("item");
+            match order.get("item") {
+                None => self.error(&item, "missing field `item`"),
+                Some(value) => {
+                    self.string(&item, value);
+                }
+            }
+
+            let quantity = path.key("quantity");
+            match order.get("quantity") {
+                None => self.error(&quantity, "missing field `quantity`"),
+                Some(value) if value.as_u64().is_some_and(|q| q <= u32::MAX.into()) => {}
+                Some(value) => self.error(
+                    &quantity,
+                    format!(
+                        "expected a quantity between 0 and {}, found {}",
+                        u32::MAX,
+                        describe(value)
+                    ),
+                ),
+            }
+        }
+    }
+
+    fn check_dependency_tables(&mut self, path: &Path, table: &Map<String, Value>) {
+        for key in DEPENDENCY_TABLES {
+            if let Some(dependencies) = table.get(*key) {
+                self.check_dependencies(&path.key(key), dependencies);
+            }
+        }
+    }
+
+    fn check_dependencies(&mut self, path: &Path, dependencies: &Value) {
+        let Some(dependencies) = self.table(path, dependencies) else {
+            return;
+        };
+
+        for (name, dependency) in dependencies {
+            self.check_dependency(&path.key(name), dependency);
+        }
+    }
+
+    fn check_dependency(&mut self, path: &Path, dependency: &Value) {
+        let dependency = match dependency {
+            Value::String(requirement) => return self.check_requirement(path, requirement),
+            Value::Object(dependency) => dependency,
+            other => {
+                return self.error(
+                    path,
+                    format!(
+                        "expected a version requirement or a dependency table, found {}",
+                        describe(other)
+                    ),
+                )
+            }
+        };
+
+        self.unknown_keys(path, dependency, DEPENDENCY_KEYS);
+
+        if let Some(workspace) = dependency.get("workspace") {
+            if workspace != &Value::Bool(true) {
+                self.error(
+                    &path.key("workspace"),
+                    format!("expected `true`, found {}", describe(workspace)),
+                );
+            }
+            for key in ["version", "path", "git", "branch", "tag", "rev", "registry"] {
+                if dependency.contains_key(key) {
+                    self.error(
+                        &path.key(key),
+                        format!("`{key}` cannot be combined with `workspace = true`"),
+                    );
+                }
+            }
+        } else if !["version", "path", "git"]
+            .iter()
+            .any(|key| dependency.contains_key(*key))
+        {
+            self.error(
+                path,
+                "dependency specifies none of `version`, `path`, `git` or `workspace`",
+            );
+        }
+
+        if let Some(value) = dependency.get("version") {
+            let version = path.key("version");
+            if let Some(requirement) = self.string(&version, value) {
+                self.check_requirement(&version, requirement);
+            }
+        }
+
+        for key in ["path", "git", "branch", "tag", "rev", "registry", "package"] {
+            if let Some(value) = dependency.get(key) {
+                self.string(&path.key(key), value);
+            }
+        }
+
+        let references: Vec<_> = ["branch", "tag", "rev"]
+            .into_iter()
+            .filter(|key| dependency.contains_key(*key))
+            .collect();
+        if references.len() > 1 {
+            self.error(
+                path,
+                "only one of `branch`, `tag` or `rev` may be specified",
+            );
+        }
+        if let Some(reference) = references.first() {
+            if !dependency.contains_key("git") {
+                self.error(
+                    &path.key(reference),
+                    format!("`{reference}` requires `git` to be specified"),
+                );
+            }
+        }
+
+        if let Some(value) = dependency.get("features") {
+            let features = path.key("features");
+            if let Some(values) = self.array(&features, value) {
+                for (i, value) in values.iter().enumerate() {
+                    self.string(&features.index(i), value);
+                }
+            }
+        }
+
+        for key in ["optional", "default-features", "default_features", "public"] {
+            if let Some(value) = dependency.get(key).filter(|value| !value.is_boolean()) {
+                self.error(
+                    &path.key(key),
+                    format!("expected a boolean, found {}", describe(value)),
+                );
+            }
+        }
+    }
+
+    fn check_requirement(&mut self, path: &Path, requirement: &str) {
+        if let Err(e) = VersionReq::parse(requirement) {
+            self.error(
+                path,
+                format!("invalid version requirement `{requirement}`: {e}"),
+            );
+        }
+    }
+
+    fn unknown_keys(&mut self, path: &Path, table: &Map<String, Value>, known: &[&str]) {
+        for key in table.keys().filter(|key| !known.contains(&key.as_str())) {
+            self.warning(&path.key(key), format!("unknown key `{key}`"));
+        }
+    }
+
+    fn table<'v>(&mut self, path: &Path, value: &'v Value) -> Option<&'v Map<String, Value>> {
+        if value.is_object() {
+            return value.as_object();
+        }
+
+        self.error(path, format!("expected a table, found {}", describe(value)));
+        None
+    }
+
+    fn array<'v>(&mut self, path: &Path, value: &'v Value) -> Option<&'v Vec<Value>> {
+        if value.is_array() {
+            return value.as_array();
+        }
+
+        self.error(
+            path,
+            format!("expected an array, found {}", describe(value)),
+        );
+        None
+    }
+
+    fn string<'v>(&mut self, path: &Path, value: &'v Value) -> Option<&'v str> {
+        if value.is_string() {
+            return value.as_str();
+        }
+
+        self.error(
+            path,
+            format!("expected a string, found {}", describe(value)),
+        );
+        None
+    }
+
+    fn error(&mut self, path: &Path, message: impl Into<String>) {
+        self.report(Severity::Error, path, message.into());
+    }
+
+    fn warning(&mut self, path: &Path, message: impl Into<String>) {
+        self.report(Severity::Warning, path, message.into());
+    }
+
+    fn report(&mut self, severity: Severity, path: &Path, message: String) {
+        let location = self
+            .document
+            .as_ref()
+            .and_then(|document| span_of(document, path))
+            .and_then(|span| self.locate(span.start));
+
+        self.push(severity, path.to_string(), message, location);
+    }
+
+    fn push(
+        &mut self,
+        severity: Severity,
+        path: String,
+        message: String,
+        location: Option<(usize, usize)>,
+    ) {
+        self.problems.push(Problem {
+            severity,
+            path,
+            message,
+            line: location.map(|(line, _)| line),
+            column: location.map(|(_, column)| column),
+        });
+    }
+
+    fn locate(&self, offset: usize) -> Option<(usize, usize)> {
+        line_column(self.source?, offset)
+    }
+}
+
+/// Finds the span of the item at `path`, or of its closest ancestor that has one.
+fn span_of(document: &ImDocument<&str>, path: &Path) -> Option<Range<usize>> {
+    let mut item = document.as_item();
+    let mut span = None;
+
+    for segment in &path.0 {
+        let next = match segment {
+            Segment::Key(key) => item.get(key.as_str()),
+            Segment::Index(index) => item.get(*index),
+        };
+        let Some(next) = next else {
+            break;
+        };
+
+        item = next;
+        span = item.span().or(span);
+    }
+
+    span
+}
+
+/// Turns a byte offset into a one-based line and column.
+fn line_column(source: &str, offset: usize) -> Option<(usize, usize)> {
+    let before = source.get(..offset)?;
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count()
+        + 1;
+
+    Some((line, column))
+}
+
+/// serde_json and serde_yaml append " at line X column Y" to their messages, which the
+/// report already carries on its own.
+fn without_location(message: String) -> String {
+    match message.rfind(" at line ") {
+        Some(end) => message[..end].to_owned(),
+        None => message,
+    }
+}
+
+/// Whether one path is the other or lies inside it.
+fn overlaps(a: &str, b: &str) -> bool {
+    let (shorter, longer) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+
+    longer
+        .strip_prefix(shorter)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.') || rest.starts_with('['))
+}
+
+/// Whether a field is `{ workspace = true }`, inheriting its value from the workspace.
+fn is_inherited(value: &Value) -> bool {
+    value
+        .as_object()
+        .is_some_and(|table| table.len() == 1 && table.get("workspace") == Some(&Value::Bool(true)))
+}
+
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Null => "nothing".to_owned(),
+        Value::Bool(b) => format!("`{b}`"),
+        Value::Number(n) => format!("`{n}`"),
+        Value::String(s) => format!("{s:?}"),
+        Value::Array(_) => "an array".to_owned(),
+        Value::Object(_) => "a table".to_owned(),
+    }
 }
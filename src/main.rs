@@ -14,6 +14,7 @@ mod day_23;
 mod day_5;
 mod day_9;
 mod day_minus_1;
+mod negotiate;
 
 #[shuttle_runtime::main]
 async fn main(
@@ -37,7 +38,7 @@ async fn main(
         .merge(day_two())
         .merge(day_five())
         .merge(day_nine())
-        .merge(day_twelve())
+        .merge(day_twelve(pool.clone()))
         .merge(day_sixteen())
         .merge(day_nineteen(pool))
         .merge(day_twentythree());
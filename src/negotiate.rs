@@ -0,0 +1,144 @@
+use axum::http::{header, HeaderMap};
+
+/// Picks the media type the client likes best out of `offered`, going by the Accept header.
+///
+/// Every offered type gets the quality of the most specific range that matches it, so
+/// `text/*;q=0, text/plain` still accepts `text/plain`. The highest quality wins. Ties go to the
+/// type whose range comes first in the header and then to whichever is offered first, which is
+/// also what a missing header or a bare `*/*` ends up with.
+///
+/// `None` when nothing offered is acceptable.
+pub fn preferred<'a>(headers: &HeaderMap, offered: &[&'a str]) -> Option<&'a str> {
+    let Some(accept) = headers.get(header::ACCEPT) else {
+        return offered.first().copied();
+    };
+    let ranges: Vec<Range> = accept
+        .to_str()
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(Range::parse)
+        .collect();
+
+    offered
+        .iter()
+        .enumerate()
+        .filter_map(|(order, media_type)| {
+            let (position, range) = ranges
+                .iter()
+                .enumerate()
+                .filter(|(_, range)| range.matches(media_type))
+                .max_by_key(|(position, range)| (range.specificity(), usize::MAX - position))?;
+
+            (range.quality > 0.0).then_some((range.quality, position, order, *media_type))
+        })
+        .max_by(|a, b| a.0.total_cmp(&b.0).then(b.1.cmp(&a.1)).then(b.2.cmp(&a.2)))
+        .map(|(.., media_type)| media_type)
+}
+
+/// One media range of an Accept header, e.g. `text/*;q=0.5`.
+///
+/// Parameters other than the quality are ignored.
+struct Range<'a> {
+    kind: &'a str,
+    subtype: &'a str,
+    quality: f32,
+}
+
+impl<'a> Range<'a> {
+    fn parse(range: &'a str) -> Option<Self> {
+        let mut parameters = range.split(';').map(str::trim);
+        let (kind, subtype) = parameters.next()?.split_once('/')?;
+        let quality = match parameters.find_map(|parameter| parameter.strip_prefix("q=")) {
+            Some(quality) => quality.parse().ok().filter(|q| (0.0..=1.0).contains(q))?,
+            None => 1.0,
+        };
+
+        Some(Self {
+            kind: kind.trim(),
+            subtype: subtype.trim(),
+            quality,
+        })
+    }
+
+    fn matches(&self, media_type: &str) -> bool {
+        let Some((kind, subtype)) = media_type.split_once('/') else {
+            return false;
+        };
+
+        match (self.kind, self.subtype) {
+            ("*", "*") => true,
+            (range, "*") => range.eq_ignore_ascii_case(kind),
+            (range, range_subtype) => {
+                range.eq_ignore_ascii_case(kind) && range_subtype.eq_ignore_ascii_case(subtype)
+            }
+        }
+    }
+
+    /// `*/*` matches the least, a full type the most.
+    fn specificity(&self) -> u8 {
+        match (self.kind, self.subtype) {
+            ("*", _) => 0,
+            (_, "*") => 1,
+            _ => 2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn pick(accept: Option<&str>) -> Option<&'static str> {
+        let mut headers = HeaderMap::new();
+        if let Some(accept) = accept {
+            headers.insert(header::ACCEPT, HeaderValue::from_str(accept).unwrap());
+        }
+
+        preferred(&headers, &["text/plain", "application/json"])
+    }
+
+    #[test]
+    fn quality_decides() {
+        assert_eq!(pick(None), Some("text/plain"));
+        assert_eq!(pick(Some("*/*")), Some("text/plain"));
+        assert_eq!(pick(Some("application/json")), Some("application/json"));
+        assert_eq!(
+            pick(Some("text/plain;q=0.5, application/json")),
+            Some("application/json")
+        );
+        assert_eq!(pick(Some("application/json;q=0, */*")), Some("text/plain"));
+        assert_eq!(pick(Some("image/png")), None);
+        assert_eq!(pick(Some("text/plain;q=0, application/json;q=0")), None);
+    }
+
+    #[test]
+    fn the_most_specific_range_counts() {
+        assert_eq!(
+            pick(Some("text/*;q=0, text/plain, application/json;q=0.5")),
+            Some("text/plain")
+        );
+        assert_eq!(
+            pick(Some("text/plain;q=0, */*;q=0.1")),
+            Some("application/json")
+        );
+        assert_eq!(
+            pick(Some("*/*;q=0.9, application/*;q=0.1")),
+            Some("text/plain")
+        );
+    }
+
+    #[test]
+    fn ties_go_by_the_header_then_by_the_offer() {
+        assert_eq!(
+            pick(Some("application/json, text/plain")),
+            Some("application/json")
+        );
+        assert_eq!(pick(Some("text/*, application/*")), Some("text/plain"));
+        assert_eq!(
+            pick(Some("TEXT/PLAIN;q=0.2, */*;q=0.2")),
+            Some("text/plain")
+        );
+    }
+}